@@ -1,11 +1,15 @@
-use crate::cohost::types::{Privacy, Project};
+use super::render::{escape_html, render_blocks, render_link, render_markdown};
+use super::tags::{normalize_tag, render_tags, tag_url};
+use crate::cohost::types::{AttachmentBlock, Block, Pagination, Post, Privacy, Project};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
 /// ActivityStreams types, as structures so that you can use serde to
 /// serialize and deserialize the types
 /// For simplicity these should be called in a compacted form with a
 /// context of:
-/// { "@context": ["https://w3.org/ns/activitystreams", "https://w3id.org/security/v1"]}
+/// { "@context": ["https://www.w3.org/ns/activitystreams", "https://w3id.org/security/v1", { ... }]}
+/// where the last element defines the extension terms we use, see `_default_context`
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ActorType {
@@ -57,10 +61,82 @@ fn _default_false() -> bool {
 fn _default_true() -> bool {
     true
 }
-fn _default_context() -> Vec<String> {
+/// The special collection used to address an object to everyone
+pub const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+/// Canonical ID of the actor bridging a cohost project. This is based on
+/// the project ID so it survives the project being renamed
+pub fn actor_url(domain: &str, project_id: u64) -> String {
+    format!("https://{}/projects/{}", domain, project_id)
+}
+
+/// URL of a project's actor by handle, which redirects to its canonical ID.
+/// Actors used these as their IDs before project IDs were used
+pub fn alias_url(domain: &str, handle: &str) -> String {
+    format!("https://{}/users/{}", domain, handle)
+}
+
+/// ID of the key used to sign requests from an actor
+pub fn key_url(actor_id: &str) -> String {
+    format!("{}#main-key", actor_id)
+}
+
+/// One of our actors, as referred to by a remote server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalActor<'a> {
+    /// By canonical ID
+    Project(u64),
+    /// By handle, as an alias or an ID from before project IDs were used
+    Handle(&'a str),
+}
+
+/// Find which of our actors a URL refers to, if it is one
+pub fn local_actor_from_url<'a>(domain: &str, url: &'a str) -> Option<LocalActor<'a>> {
+    let path = url.strip_prefix("https://")?.strip_prefix(domain)?;
+    if let Some(project_id) = path.strip_prefix("/projects/") {
+        return project_id.parse().ok().map(LocalActor::Project);
+    }
+    path.strip_prefix("/users/")
+        .filter(|handle| !handle.is_empty() && !handle.contains('/'))
+        .map(LocalActor::Handle)
+}
+
+/// Canonical ID of the object bridging a cohost post
+pub fn post_url(domain: &str, project_id: u64, post_id: u64) -> String {
+    format!("{}/posts/{}", actor_url(domain, project_id), post_id)
+}
+
+/// Who a post by `actor` is addressed to, as `(to, cc)`. Only approved
+/// followers may see `private` posts, as with posts by private projects
+fn post_audience(actor: &str, private: bool) -> (Vec<String>, Vec<String>) {
+    let followers = format!("{}/followers", actor);
+    if private {
+        (vec![followers], vec![])
+    } else {
+        (vec![PUBLIC.to_string()], vec![followers])
+    }
+}
+
+fn _default_context() -> Vec<Value> {
     vec![
-        "https://w3.org/ns/activitystreams".to_string(),
-        "https://w3id.org/security/v1".to_string(),
+        json!("https://www.w3.org/ns/activitystreams"),
+        json!("https://w3id.org/security/v1"),
+        // Extensions used by Mastodon and others which aren't in the above
+        json!({
+            "manuallyApprovesFollowers": "as:manuallyApprovesFollowers",
+            "sensitive": "as:sensitive",
+            "Hashtag": "as:Hashtag",
+            "quoteUrl": "as:quoteUrl",
+            "toot": "http://joinmastodon.org/ns#",
+            "featured": { "@id": "toot:featured", "@type": "@id" },
+            "featuredTags": { "@id": "toot:featuredTags", "@type": "@id" },
+            "discoverable": "toot:discoverable",
+            "alsoKnownAs": { "@id": "as:alsoKnownAs", "@type": "@id" },
+            "movedTo": { "@id": "as:movedTo", "@type": "@id" },
+            "schema": "http://schema.org#",
+            "PropertyValue": "schema:PropertyValue",
+            "value": "schema:value",
+        }),
     ]
 }
 
@@ -71,7 +147,7 @@ pub struct ActorPage {
     /// care about this when deserializing since we should
     /// have already reduced it
     #[serde(rename = "@context", default = "_default_context", skip_deserializing)]
-    pub context: Vec<String>,
+    pub context: Vec<Value>,
     pub id: String,
     #[serde(rename = "type")]
     pub actor_type: ActorType,
@@ -85,12 +161,19 @@ pub struct ActorPage {
     pub featured: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub featured_tags: Option<String>,
+    /// Other IDs of the same actor, which remote servers need to see to
+    /// accept a `Move` from one of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub also_known_as: Vec<String>,
+    /// The actor which replaced this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moved_to: Option<String>,
     pub preferred_username: String,
     pub name: String,
     pub summary: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub url: Option<String>,
-    #[serde(rename = "as:manuallyApprovesFollowers", default = "_default_true")]
+    #[serde(default = "_default_true")]
     pub manually_approves_followers: bool,
     #[serde(default = "_default_true")]
     pub discoverable: bool,
@@ -101,35 +184,718 @@ pub struct ActorPage {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub tag: Vec<String>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
-    pub attachment: Vec<String>,
+    pub attachment: Vec<ActorAttachment>,
+    /// Avatar
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<Document>,
+    /// Header image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<Document>,
     #[serde(skip_serializing_if = "Endpoints::is_empty")]
     pub endpoints: Endpoints,
+    pub public_key: PublicKey,
+}
+
+/// Extra information shown on an actor's profile
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum ActorAttachment {
+    /// A field in the profile metadata table. `value` may contain HTML
+    PropertyValue { name: String, value: String },
 }
 
 impl ActorPage {
-    pub fn with_project(domain: &str, project: &Project) -> Self {
+    /// Convert a cohost project. `handles` are every handle the project has
+    /// been known by, which become aliases of the actor
+    pub fn with_project(
+        domain: &str,
+        project: &Project,
+        handles: &[String],
+        public_key_pem: String,
+    ) -> Self {
+        let id = actor_url(domain, project.project_id);
+
+        let mut summary = String::new();
+        if !project.headline.is_empty() {
+            summary.push_str(&format!("<p>{}</p>", escape_html(&project.headline)));
+        }
+        summary.push_str(&render_markdown(&project.description));
+
+        let mut attachment = Vec::new();
+        if let Some(pronouns) = project.pronouns.as_ref().filter(|p| !p.is_empty()) {
+            attachment.push(ActorAttachment::PropertyValue {
+                name: "Pronouns".to_string(),
+                value: escape_html(pronouns),
+            });
+        }
+        if let Some(link) = project.url.as_deref().and_then(render_link) {
+            attachment.push(ActorAttachment::PropertyValue {
+                name: "Link".to_string(),
+                value: link,
+            });
+        }
+
         Self {
+            public_key: PublicKey {
+                id: key_url(&id),
+                owner: id.clone(),
+                public_key_pem,
+            },
             context: _default_context(),
-            id: format!("https://{}/users/{}", domain, &project.handle),
             actor_type: ActorType::Person,
-            following: format!("https://{}/users/{}/following", domain, &project.handle),
-            followers: format!("https://{}/users/{}/followers", domain, &project.handle),
-            inbox: format!("https://{}/users/{}/inbox", domain, &project.handle),
-            outbox: format!("https://{}/users/{}/outbox", domain, &project.handle),
+            following: format!("{}/following", &id),
+            followers: format!("{}/followers", &id),
+            inbox: format!("{}/inbox", &id),
+            outbox: format!("{}/outbox", &id),
+            featured: Some(format!("{}/featured", &id)),
+            id,
             liked: None,
-            featured: None,
             featured_tags: None,
+            also_known_as: handles
+                .iter()
+                .map(|handle| alias_url(domain, handle))
+                .collect(),
+            moved_to: None,
             preferred_username: project.handle.to_string(),
             name: project.display_name.to_string(),
-            summary: project.headline.to_string(),
-            url: None,
+            summary,
+            url: Some(format!("https://cohost.org/{}", &project.handle)),
             manually_approves_followers: project.privacy == Privacy::Private,
             discoverable: true,
             published: None,
             devices: None,
             tag: vec![],
-            attachment: vec![],
-            endpoints: Endpoints::default(),
+            attachment,
+            icon: (!project.avatar_url.is_empty()).then(|| Document::image(&project.avatar_url)),
+            image: project
+                .header_url
+                .as_ref()
+                .filter(|url| !url.is_empty())
+                .map(|url| Document::image(url)),
+            endpoints: Endpoints {
+                shared_inbox: Some(format!("https://{}/inbox", domain)),
+                ..Endpoints::default()
+            },
+        }
+    }
+
+    /// The actor which was known as `/users/<handle>` before IDs were based
+    /// on project IDs, moved to `actor`. It has its own key ID so servers
+    /// following it can check the `Move` it signs
+    pub fn legacy(actor: &ActorPage, domain: &str, handle: &str) -> Self {
+        let id = alias_url(domain, handle);
+        Self {
+            public_key: PublicKey {
+                id: key_url(&id),
+                owner: id.clone(),
+                public_key_pem: actor.public_key.public_key_pem.clone(),
+            },
+            following: format!("{}/following", &id),
+            followers: format!("{}/followers", &id),
+            inbox: format!("{}/inbox", &id),
+            outbox: format!("{}/outbox", &id),
+            featured: Some(format!("{}/featured", &id)),
+            also_known_as: vec![],
+            moved_to: Some(actor.id.clone()),
+            id,
+            ..actor.clone()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum CollectionType {
+    OrderedCollection,
+    OrderedCollectionPage,
+}
+
+/// An ordered collection, which is not paged itself but links to the first
+/// [page](OrderedCollectionPage) of items
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderedCollection {
+    #[serde(rename = "@context", default = "_default_context", skip_deserializing)]
+    pub context: Vec<Value>,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub collection_type: CollectionType,
+    /// Number of items in the collection, if known. Cohost does not tell us
+    /// how many posts a project has, so this is often absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_items: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last: Option<String>,
+    /// Items, for small collections which aren't paged
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ordered_items: Vec<Value>,
+}
+
+/// A single page of an [OrderedCollection]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderedCollectionPage<T> {
+    #[serde(rename = "@context", default = "_default_context", skip_deserializing)]
+    pub context: Vec<Value>,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub collection_type: CollectionType,
+    /// The collection this page belongs to
+    pub part_of: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+    pub ordered_items: Vec<T>,
+}
+
+impl OrderedCollection {
+    /// Create a paged collection, where the first page is `page=0`
+    pub fn paged(id: String, total_items: Option<u64>) -> Self {
+        Self {
+            context: _default_context(),
+            first: Some(format!("{}?page=0", &id)),
+            id,
+            collection_type: CollectionType::OrderedCollection,
+            total_items,
+            last: None,
+            ordered_items: vec![],
+        }
+    }
+}
+
+impl OrderedCollection {
+    /// Create a collection which only exposes how many items it has
+    pub fn with_total_items(id: String, total_items: u64) -> Self {
+        Self {
+            context: _default_context(),
+            id,
+            collection_type: CollectionType::OrderedCollection,
+            total_items: Some(total_items),
+            first: None,
+            last: None,
+            ordered_items: vec![],
+        }
+    }
+
+    /// Create a collection which includes all of its items
+    pub fn with_items(id: String, ordered_items: Vec<Value>) -> Self {
+        Self {
+            context: _default_context(),
+            id,
+            collection_type: CollectionType::OrderedCollection,
+            total_items: Some(ordered_items.len() as u64),
+            first: None,
+            last: None,
+            ordered_items,
+        }
+    }
+}
+
+impl<T> OrderedCollectionPage<T> {
+    /// Create a page of the collection `part_of` from cohost pagination info
+    pub fn with_pagination(
+        part_of: String,
+        pagination: &Pagination,
+        ordered_items: Vec<T>,
+    ) -> Self {
+        Self {
+            context: _default_context(),
+            id: format!("{}?page={}", &part_of, pagination.current_page),
+            collection_type: CollectionType::OrderedCollectionPage,
+            next: pagination
+                .more_pages_forward
+                .then(|| format!("{}?page={}", &part_of, pagination.next_page)),
+            prev: (pagination.current_page > 0)
+                .then(|| format!("{}?page={}", &part_of, pagination.current_page - 1)),
+            part_of,
+            ordered_items,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ObjectType {
+    Note,
+    Document,
+    Image,
+    Video,
+    Audio,
+    Tombstone,
+}
+
+/// File extensions of media commonly attached to cohost posts. Cohost doesn't
+/// tell us the type and we don't want to make a request per attachment, so
+/// guess from the URL instead
+const MEDIA_TYPES: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+    ("bmp", "image/bmp"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("mov", "video/quicktime"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    ("m4a", "audio/mp4"),
+    ("pdf", "application/pdf"),
+];
+
+/// Guess the media type of a file from the extension in its URL
+pub fn media_type_for_url(url: &str) -> Option<&'static str> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let filename = path.rsplit('/').next().unwrap_or(path);
+    let (_, extension) = filename.rsplit_once('.')?;
+    let extension = extension.to_ascii_lowercase();
+    MEDIA_TYPES
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, media_type)| *media_type)
+}
+
+/// A file attached to an object, or an image used as an icon
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Document {
+    #[serde(rename = "type")]
+    pub object_type: ObjectType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    pub url: String,
+    /// Alt text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Preview of the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<Box<Document>>,
+}
+
+impl Document {
+    /// An image at the given URL, with no alt text
+    pub fn image(url: &str) -> Self {
+        Self {
+            object_type: ObjectType::Image,
+            media_type: media_type_for_url(url).map(str::to_string),
+            url: url.to_string(),
+            name: None,
+            icon: None,
+        }
+    }
+
+    pub fn with_attachment(attachment: &AttachmentBlock) -> Self {
+        let media_type = media_type_for_url(&attachment.file_url);
+        let object_type = match media_type.and_then(|media_type| media_type.split_once('/')) {
+            Some(("image", _)) => ObjectType::Image,
+            Some(("video", _)) => ObjectType::Video,
+            Some(("audio", _)) => ObjectType::Audio,
+            _ => ObjectType::Document,
+        };
+        Self {
+            object_type,
+            media_type: media_type.map(str::to_string),
+            url: attachment.file_url.clone(),
+            name: (!attachment.alt_text.is_empty()).then(|| attachment.alt_text.clone()),
+            icon: (!attachment.preview_url.is_empty())
+                .then(|| Box::new(Document::image(&attachment.preview_url))),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum Tag {
+    Hashtag {
+        /// Name of the hashtag, including the leading `#`.
+        /// Normalized with [normalize_tag], so it may differ from the cohost tag
+        name: String,
+        href: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Note {
+    #[serde(rename = "@context", default = "_default_context", skip_deserializing)]
+    pub context: Vec<Value>,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub object_type: ObjectType,
+    pub attributed_to: String,
+    /// Title of the post. Most servers only show this for articles, so the
+    /// headline is also repeated at the top of [content](Self::content)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Content warning, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Whether the content should be hidden until the reader chooses to show it
+    #[serde(default)]
+    pub sensitive: bool,
+    /// HTML content of the post
+    pub content: String,
+    pub published: chrono::DateTime<chrono::Utc>,
+    /// When the post was last edited, if we know it was
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated: Option<chrono::DateTime<chrono::Utc>>,
+    /// Link to the post on cohost
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub cc: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tag: Vec<Tag>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachment: Vec<Document>,
+    /// ID of the object this note quotes, as used by Fedibird and others
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote_url: Option<String>,
+    /// Same as [quote_url](Self::quote_url), for Misskey
+    #[serde(rename = "_misskey_quote", skip_serializing_if = "Option::is_none")]
+    pub misskey_quote: Option<String>,
+}
+
+impl Note {
+    /// Convert a cohost post. `adult_content_warning` is used as the content
+    /// warning of 18+ posts which don't have one. `bridged_share` is the post
+    /// this post shares if that was bridged too, which is quoted. Other shared
+    /// posts are linked to on cohost
+    pub fn with_post(
+        domain: &str,
+        post: &Post,
+        adult_content_warning: &str,
+        bridged_share: Option<&Post>,
+    ) -> Self {
+        let actor = actor_url(domain, post.posting_project.project_id);
+
+        let mut content = String::new();
+        if !post.headline.is_empty() {
+            content.push_str(&format!(
+                "<p><strong>{}</strong></p>",
+                escape_html(&post.headline)
+            ));
+        }
+        content.push_str(&render_blocks(&post.blocks));
+        content.push_str(&render_tags(domain, &post.tags));
+
+        // Servers which don't understand quotes still get a link to the shared
+        // post, which is on cohost if its project isn't bridged
+        let quote_url = bridged_share
+            .map(|shared| post_url(domain, shared.posting_project.project_id, shared.post_id));
+        let shared_url = quote_url.clone().or_else(|| {
+            post.shared_post()
+                .map(|shared| shared.single_post_page_url.clone())
+        });
+        if let Some(shared_url) = shared_url {
+            let shared_url = escape_html(&shared_url);
+            content.push_str(&format!(
+                "<p>RE: <a href=\"{}\">{}</a></p>",
+                shared_url, shared_url
+            ));
+        }
+
+        let (to, cc) = post_audience(&actor, post.posting_project.privacy == Privacy::Private);
+
+        let summary = if !post.cws.is_empty() {
+            Some(post.cws.join(", "))
+        } else if post.effective_adult_content && !adult_content_warning.is_empty() {
+            Some(adult_content_warning.to_string())
+        } else {
+            None
+        };
+
+        Self {
+            context: _default_context(),
+            id: post_url(domain, post.posting_project.project_id, post.post_id),
+            object_type: ObjectType::Note,
+            name: (!post.headline.is_empty()).then(|| post.headline.clone()),
+            sensitive: summary.is_some() || post.effective_adult_content,
+            summary,
+            content,
+            published: post.published_at,
+            updated: None,
+            url: Some(post.single_post_page_url.clone()),
+            to,
+            cc,
+            tag: post
+                .tags
+                .iter()
+                .filter_map(|tag| normalize_tag(tag))
+                .map(|hashtag| Tag::Hashtag {
+                    href: tag_url(domain, &hashtag),
+                    name: format!("#{}", hashtag),
+                })
+                .collect(),
+            attachment: post
+                .blocks
+                .iter()
+                .filter_map(|block| match block {
+                    Block::Attachment { attachment } => Some(Document::with_attachment(attachment)),
+                    Block::Markdown { .. } | Block::Ask { .. } => None,
+                })
+                .collect(),
+            misskey_quote: quote_url.clone(),
+            quote_url,
+            attributed_to: actor,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ActivityType {
+    Announce,
+    Create,
+    Update,
+    Delete,
+    Follow,
+    Accept,
+    Undo,
+    Move,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Activity<T> {
+    #[serde(rename = "@context", default = "_default_context", skip_deserializing)]
+    pub context: Vec<Value>,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub activity_type: ActivityType,
+    pub actor: String,
+    pub object: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub cc: Vec<String>,
+    /// Where the object of a `Move` moved to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+impl Activity<Note> {
+    /// Wrap a note in a `Create` activity with the same audience
+    pub fn create(note: Note) -> Self {
+        Self {
+            context: _default_context(),
+            id: format!("{}/activity", &note.id),
+            activity_type: ActivityType::Create,
+            actor: note.attributed_to.clone(),
+            published: Some(note.published),
+            to: note.to.clone(),
+            cc: note.cc.clone(),
+            object: note,
+            target: None,
+        }
+    }
+
+    /// Replace a note which was edited at `updated`, with the same audience
+    pub fn update(mut note: Note, updated: chrono::DateTime<chrono::Utc>) -> Self {
+        note.updated = Some(updated);
+        Self {
+            context: _default_context(),
+            id: format!("{}#updates/{}", &note.id, updated.timestamp()),
+            activity_type: ActivityType::Update,
+            actor: note.attributed_to.clone(),
+            published: Some(updated),
+            to: note.to.clone(),
+            cc: note.cc.clone(),
+            object: note,
+            target: None,
+        }
+    }
+}
+
+impl Activity<String> {
+    /// Share an object with the sharer's followers
+    pub fn announce(
+        id: String,
+        actor: String,
+        object: String,
+        object_actor: String,
+        published: chrono::DateTime<chrono::Utc>,
+    ) -> Self {
+        Self {
+            context: _default_context(),
+            id,
+            activity_type: ActivityType::Announce,
+            to: vec![PUBLIC.to_string()],
+            cc: vec![format!("{}/followers", &actor), object_actor],
+            actor,
+            object,
+            published: Some(published),
+            target: None,
+        }
+    }
+
+    /// Tell followers that an actor no longer exists
+    pub fn delete_actor(actor: String) -> Self {
+        Self {
+            context: _default_context(),
+            id: format!("{}#delete", &actor),
+            activity_type: ActivityType::Delete,
+            to: vec![PUBLIC.to_string()],
+            cc: vec![format!("{}/followers", &actor)],
+            object: actor.clone(),
+            actor,
+            published: Some(chrono::Utc::now()),
+            target: None,
+        }
+    }
+
+    /// Tell followers of `origin` that it is now `target`, so their servers
+    /// follow `target` instead. `target` has to list `origin` in its
+    /// `alsoKnownAs`
+    pub fn move_actor(origin: String, target: String) -> Self {
+        Self {
+            context: _default_context(),
+            id: format!("{}#moves/{}", &origin, chrono::Utc::now().timestamp()),
+            activity_type: ActivityType::Move,
+            to: vec![format!("{}/followers", &origin)],
+            cc: vec![],
+            object: origin.clone(),
+            actor: origin,
+            published: Some(chrono::Utc::now()),
+            target: Some(target),
+        }
+    }
+}
+
+impl Activity<ActorPage> {
+    /// Tell followers that an actor's profile changed
+    pub fn update_actor(actor: ActorPage) -> Self {
+        let updated = chrono::Utc::now();
+        Self {
+            context: _default_context(),
+            id: format!("{}#updates/{}", &actor.id, updated.timestamp()),
+            activity_type: ActivityType::Update,
+            to: vec![PUBLIC.to_string()],
+            cc: vec![actor.followers.clone()],
+            actor: actor.id.clone(),
+            object: actor,
+            published: Some(updated),
+            target: None,
+        }
+    }
+}
+
+/// The activity representing a cohost post in an outbox
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PostActivity {
+    /// An original post, or a share which adds its own content
+    Create(Box<Activity<Note>>),
+    /// A transparent share, which only shares another post
+    Announce(Activity<String>),
+}
+
+impl PostActivity {
+    /// Convert a cohost post. Transparent shares of bridged posts are
+    /// announced, anything else becomes a Note as in [Note::with_post]
+    pub fn with_post(
+        domain: &str,
+        post: &Post,
+        adult_content_warning: &str,
+        bridged_share: Option<&Post>,
+    ) -> Self {
+        match (post.transparent_share_of_post_id, bridged_share) {
+            (Some(_), Some(shared)) => {
+                let shared_actor = actor_url(domain, shared.posting_project.project_id);
+                let mut announce = Activity::announce(
+                    format!(
+                        "{}/activity",
+                        post_url(domain, post.posting_project.project_id, post.post_id)
+                    ),
+                    actor_url(domain, post.posting_project.project_id),
+                    post_url(domain, shared.posting_project.project_id, shared.post_id),
+                    shared_actor.clone(),
+                    post.published_at,
+                );
+                if post.posting_project.privacy == Privacy::Private {
+                    announce.to = vec![format!("{}/followers", &announce.actor)];
+                    announce.cc = vec![shared_actor];
+                }
+                Self::Announce(announce)
+            }
+            _ => Self::Create(Box::new(Activity::create(Note::with_post(
+                domain,
+                post,
+                adult_content_warning,
+                bridged_share,
+            )))),
+        }
+    }
+}
+
+impl<T> Activity<T> {
+    /// Accept an activity, such as a follow, on behalf of `actor`
+    pub fn accept(actor: String, object: T) -> Self {
+        Self {
+            context: _default_context(),
+            id: format!("{}#accepts/{:016x}", &actor, rand::random::<u64>()),
+            activity_type: ActivityType::Accept,
+            actor,
+            object,
+            published: None,
+            to: vec![],
+            cc: vec![],
+            target: None,
+        }
+    }
+}
+
+impl Activity<Tombstone> {
+    /// Tell followers of `actor` that one of its posts was deleted. It is
+    /// addressed like the post was, so `private` posts stay private
+    pub fn delete(actor: String, tombstone: Tombstone, private: bool) -> Self {
+        let (to, cc) = post_audience(&actor, private);
+        Self {
+            context: _default_context(),
+            id: format!("{}#delete", &tombstone.id),
+            activity_type: ActivityType::Delete,
+            to,
+            cc,
+            actor,
+            published: tombstone.deleted,
+            object: tombstone,
+            target: None,
+        }
+    }
+}
+
+/// Placeholder for an object which has been deleted
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Tombstone {
+    #[serde(rename = "@context", default = "_default_context", skip_deserializing)]
+    pub context: Vec<Value>,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub object_type: ObjectType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub former_type: Option<ObjectType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Tombstone {
+    /// Tombstone for a deleted note
+    pub fn note(id: String, deleted: Option<chrono::DateTime<chrono::Utc>>) -> Self {
+        Self {
+            context: _default_context(),
+            id,
+            object_type: ObjectType::Tombstone,
+            former_type: Some(ObjectType::Note),
+            deleted,
         }
     }
 }
@@ -0,0 +1,252 @@
+use super::signature::{digest_header, http_date, sign_request};
+use crate::cohost::api::{is_loopback, VERSION};
+use crate::store::{CachedActor, Store};
+use anyhow::Context;
+use hyper::{
+    client::HttpConnector,
+    header::{self, HeaderValue},
+    Body, Client, Method, Request, Response, Uri,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use rsa::RsaPrivateKey;
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{debug, instrument};
+
+/// Media type used when requesting ActivityStreams documents
+pub const ACTIVITY_JSON: &str = "application/activity+json";
+
+/// How long to use a remote actor document before fetching it again
+const ACTOR_CACHE_HOURS: i64 = 24;
+
+/// Public key of a remote actor, as found in its actor document
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteKey {
+    pub id: String,
+    pub owner: String,
+    pub public_key_pem: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteEndpoints {
+    pub shared_inbox: Option<String>,
+}
+
+/// The parts of a remote actor we need in order to deliver to it
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteActor {
+    pub id: String,
+    pub inbox: String,
+    #[serde(default)]
+    pub endpoints: RemoteEndpoints,
+}
+
+/// Client for talking to other ActivityPub servers
+#[derive(Clone, Debug)]
+pub struct FederationClient {
+    user_agent: HeaderValue,
+    http_client: Client<HttpsConnector<HttpConnector>>,
+    /// Client for plain HTTP, which is only used for loopback addresses so
+    /// remote servers can't have us make unencrypted requests elsewhere
+    loopback_client: Client<HttpConnector>,
+}
+
+impl FederationClient {
+    pub fn new(domain: &str) -> Self {
+        let conn = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_only()
+            .enable_http1()
+            .enable_http2()
+            .build();
+
+        Self {
+            user_agent: HeaderValue::from_str(&format!(
+                "cobridge/{} (+https://{}/)",
+                VERSION, domain
+            ))
+            .unwrap(),
+            http_client: Client::builder().build(conn),
+            loopback_client: Client::new(),
+        }
+    }
+
+    /// Send a request over HTTPS, or plain HTTP if it is to a loopback address
+    async fn send(&self, request: Request<Body>) -> anyhow::Result<Response<Body>> {
+        let uri = request.uri();
+        match uri.scheme_str() {
+            Some("https") => Ok(self.http_client.request(request).await?),
+            Some("http") if uri.host().is_some_and(is_loopback) => {
+                Ok(self.loopback_client.request(request).await?)
+            }
+            Some("http") => Err(anyhow::anyhow!(
+                "plain HTTP is only allowed for loopback addresses"
+            )),
+            _ => Err(anyhow::anyhow!("URL must be http or https")),
+        }
+    }
+
+    pub fn request_base(&self, uri: Uri) -> http::request::Builder {
+        Request::builder()
+            .uri(uri)
+            .header(header::USER_AGENT, self.user_agent.clone())
+    }
+
+    pub fn http_client(&self) -> &Client<HttpsConnector<HttpConnector>> {
+        &self.http_client
+    }
+
+    /// Fetch an ActivityStreams document
+    #[instrument(skip(self), err)]
+    pub async fn fetch_json(&self, url: &str) -> anyhow::Result<Value> {
+        debug!("fetching remote object");
+        let uri = Uri::try_from(url).context("invalid object URL")?;
+        let request = self
+            .request_base(uri)
+            .header(header::ACCEPT, ACTIVITY_JSON)
+            .body(Body::empty())?;
+
+        let response = self
+            .send(request)
+            .await
+            .context("failed to fetch remote object")?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "remote server returned status {}",
+                response.status()
+            ));
+        }
+
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await?)
+            .context("failed to parse remote object as JSON")
+    }
+
+    /// Fetch an actor document, using the copy in the store if it is recent enough.
+    /// `refresh` forces a fetch, for example when a cached key no longer matches
+    pub async fn fetch_actor_document(
+        &self,
+        store: &dyn Store,
+        url: &str,
+        refresh: bool,
+    ) -> anyhow::Result<Value> {
+        let now = chrono::Utc::now();
+        if !refresh {
+            if let Some(cached) = store.cached_actor(url)? {
+                if now - cached.fetched < chrono::Duration::hours(ACTOR_CACHE_HOURS) {
+                    return serde_json::from_str(&cached.document)
+                        .context("failed to parse cached actor");
+                }
+            }
+        }
+
+        let document = self.fetch_json(url).await?;
+        store.cache_actor(
+            url,
+            &CachedActor {
+                document: document.to_string(),
+                fetched: now,
+            },
+        )?;
+        Ok(document)
+    }
+
+    pub async fn fetch_actor(&self, store: &dyn Store, url: &str) -> anyhow::Result<RemoteActor> {
+        serde_json::from_value(self.fetch_actor_document(store, url, false).await?)
+            .context("failed to parse remote actor")
+    }
+
+    /// Send an activity to an inbox, signed with the sending actor's key.
+    /// Returns the status given by the remote server, even if it is an error
+    #[instrument(skip(self, activity, key), err)]
+    pub async fn post_activity(
+        &self,
+        inbox: &str,
+        activity: &[u8],
+        key_id: &str,
+        key: &RsaPrivateKey,
+    ) -> anyhow::Result<http::StatusCode> {
+        debug!("posting activity");
+        let uri = Uri::try_from(inbox).context("invalid inbox URL")?;
+        let host = uri
+            .authority()
+            .ok_or_else(|| anyhow::anyhow!("inbox URL has no host"))?
+            .to_string();
+        let date = http_date(chrono::Utc::now());
+        let digest = digest_header(activity);
+        let signature = sign_request(
+            key_id,
+            key,
+            &Method::POST,
+            &uri,
+            &[
+                ("host", &host),
+                ("date", &date),
+                ("digest", &digest),
+                ("content-type", ACTIVITY_JSON),
+            ],
+        );
+
+        let request = self
+            .request_base(uri)
+            .method(Method::POST)
+            .header(header::HOST, host)
+            .header(header::DATE, date)
+            .header("digest", digest)
+            .header(header::CONTENT_TYPE, ACTIVITY_JSON)
+            .header("signature", signature)
+            .body(Body::from(activity.to_vec()))?;
+
+        let response = self
+            .send(request)
+            .await
+            .context("failed to post activity")?;
+        Ok(response.status())
+    }
+
+    /// Fetch the public key with the given ID. The key ID must be a
+    /// fragment of the owning actor's ID, with the key in the actor's
+    /// `publicKey` field. The actor document must have the ID it was
+    /// fetched from, and the key must be owned by it, so a server can't
+    /// publish keys which speak for actors elsewhere
+    pub async fn fetch_public_key(
+        &self,
+        store: &dyn Store,
+        key_id: &str,
+        refresh: bool,
+    ) -> anyhow::Result<RemoteKey> {
+        let url = key_id.split('#').next().unwrap_or(key_id);
+        let mut document = self.fetch_actor_document(store, url, refresh).await?;
+        if document.get("id").and_then(Value::as_str) != Some(url) {
+            return Err(anyhow::anyhow!(
+                "remote actor has id {} instead of {}",
+                document.get("id").unwrap_or(&Value::Null),
+                url
+            ));
+        }
+        let key_value = document
+            .get_mut("publicKey")
+            .ok_or_else(|| anyhow::anyhow!("remote actor has no public key"))?
+            .take();
+        let key: RemoteKey =
+            serde_json::from_value(key_value).context("remote actor has no public key")?;
+        if key.id != key_id {
+            return Err(anyhow::anyhow!(
+                "remote key has id {} instead of {}",
+                key.id,
+                key_id
+            ));
+        }
+        if key.owner != url {
+            return Err(anyhow::anyhow!(
+                "remote key {} is owned by {} instead of {}",
+                key_id,
+                key.owner,
+                url
+            ));
+        }
+        Ok(key)
+    }
+}
@@ -0,0 +1,250 @@
+//! Persistent queue of outgoing activities. Deliveries are retried with
+//! exponential backoff until they succeed or become too old to be useful.
+
+use super::{activitystreams::key_url, server::State};
+use crate::store::Store;
+use anyhow::Context;
+use futures::StreamExt;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{debug, error, info, warn};
+
+/// Maximum number of requests in flight at once
+const CONCURRENCY: usize = 16;
+/// Delay before the first retry, doubled for each further attempt
+pub const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// Longest we will wait between two attempts of the same delivery
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(6 * 60 * 60);
+/// How often to check the queue if nothing wakes the worker up
+const IDLE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct DeliveryConfig {
+    /// Maximum time to wait for a remote server to respond
+    pub timeout: Duration,
+    /// Give up on deliveries which are older than this
+    pub max_age: Duration,
+}
+
+/// An activity waiting to be sent to a remote inbox
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Delivery {
+    pub id: String,
+    /// Project whose actor is sending the activity, used to find the signing key
+    pub project_id: u64,
+    /// ID of the sending actor
+    pub actor: String,
+    pub inbox: String,
+    /// Serialized activity
+    pub activity: String,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub attempts: u32,
+    pub next_attempt: chrono::DateTime<chrono::Utc>,
+}
+
+/// Outcome of a single attempt to deliver an activity
+enum Attempt {
+    Delivered,
+    /// Failed in a way which may succeed later, such as a timeout or 5xx
+    Retry(String),
+    /// Failed in a way which won't change, such as a 4xx
+    Fail(String),
+}
+
+/// Queue of outgoing activities, sent in the background by [run].
+/// Pending deliveries are kept in the [Store] so they survive restarts
+pub struct DeliveryQueue {
+    store: Arc<dyn Store>,
+    wake: Notify,
+}
+
+impl DeliveryQueue {
+    pub fn new(store: Arc<dyn Store>) -> Self {
+        Self {
+            store,
+            wake: Notify::new(),
+        }
+    }
+
+    fn enqueue_serialized(
+        &self,
+        project_id: u64,
+        actor: &str,
+        inboxes: Vec<String>,
+        activity: String,
+    ) -> anyhow::Result<()> {
+        let now = chrono::Utc::now();
+        for inbox in inboxes {
+            self.store.save_delivery(&Delivery {
+                id: format!("{}-{:016x}", now.timestamp_millis(), rand::random::<u64>()),
+                project_id,
+                actor: actor.to_string(),
+                inbox,
+                activity: activity.clone(),
+                created: now,
+                attempts: 0,
+                next_attempt: now,
+            })?;
+        }
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    /// Send an activity to a single inbox
+    pub fn deliver<T: Serialize>(
+        &self,
+        project_id: u64,
+        actor: &str,
+        inbox: &str,
+        activity: &T,
+    ) -> anyhow::Result<()> {
+        let activity = serde_json::to_string(activity).context("failed to serialize activity")?;
+        self.enqueue_serialized(project_id, actor, vec![inbox.to_string()], activity)
+    }
+
+    /// Send an activity to every follower of a project.
+    /// Followers on the same server are sent a single copy through their shared inbox
+    pub fn deliver_to_followers<T: Serialize>(
+        &self,
+        project_id: u64,
+        actor: &str,
+        activity: &T,
+    ) -> anyhow::Result<()> {
+        let activity = serde_json::to_string(activity).context("failed to serialize activity")?;
+        let mut inboxes = self
+            .store
+            .followers(project_id)?
+            .into_iter()
+            .map(|follower| follower.shared_inbox.unwrap_or(follower.inbox))
+            .collect::<Vec<_>>();
+        inboxes.sort();
+        inboxes.dedup();
+
+        self.enqueue_serialized(project_id, actor, inboxes, activity)
+    }
+
+    /// Deliveries which should be attempted now, and when the next one after those is due
+    fn due(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> anyhow::Result<(Vec<Delivery>, Option<chrono::DateTime<chrono::Utc>>)> {
+        let (due, waiting): (Vec<_>, Vec<_>) = self
+            .store
+            .deliveries()?
+            .into_iter()
+            .partition(|delivery| delivery.next_attempt <= now);
+        let next = waiting.iter().map(|delivery| delivery.next_attempt).min();
+        Ok((due, next))
+    }
+}
+
+/// Send queued deliveries forever
+pub async fn run(state: Arc<State>, config: DeliveryConfig) {
+    loop {
+        let next = send_due(&state, &config).await;
+        let wait = next
+            .and_then(|next| (next - chrono::Utc::now()).to_std().ok())
+            .unwrap_or(IDLE_INTERVAL)
+            .min(IDLE_INTERVAL);
+        tokio::select! {
+            _ = state.delivery.wake.notified() => (),
+            _ = tokio::time::sleep(wait) => (),
+        }
+    }
+}
+
+/// Attempt every delivery which is due, returning when the next one after
+/// those will be due
+pub async fn send_due(
+    state: &State,
+    config: &DeliveryConfig,
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    let (due, next) = match state.delivery.due(chrono::Utc::now()) {
+        Ok(due) => due,
+        Err(err) => {
+            error!("failed to read delivery queue: {:#}", err);
+            (Vec::new(), None)
+        }
+    };
+    futures::stream::iter(due)
+        .for_each_concurrent(CONCURRENCY, |delivery| async move {
+            if let Err(err) = process(state, config, delivery).await {
+                error!("failed to update delivery queue: {:#}", err);
+            }
+        })
+        .await;
+    next
+}
+
+/// Attempt a delivery and update the queue based on the outcome
+async fn process(
+    state: &State,
+    config: &DeliveryConfig,
+    mut delivery: Delivery,
+) -> anyhow::Result<()> {
+    match attempt(state, config, &delivery).await {
+        Attempt::Delivered => {
+            debug!("delivered {} to {}", &delivery.id, &delivery.inbox);
+            state.store.remove_delivery(&delivery.id)
+        }
+        Attempt::Fail(reason) => {
+            warn!("giving up on delivery to {}: {}", &delivery.inbox, reason);
+            state.store.remove_delivery(&delivery.id)
+        }
+        Attempt::Retry(reason) => {
+            let now = chrono::Utc::now();
+            let age = (now - delivery.created).to_std().unwrap_or_default();
+            if age > config.max_age {
+                warn!(
+                    "giving up on delivery to {} after {} attempts: {}",
+                    &delivery.inbox,
+                    delivery.attempts + 1,
+                    reason
+                );
+                return state.store.remove_delivery(&delivery.id);
+            }
+
+            let delay = INITIAL_RETRY_DELAY
+                .saturating_mul(2u32.saturating_pow(delivery.attempts))
+                .min(MAX_RETRY_DELAY);
+            delivery.attempts += 1;
+            delivery.next_attempt = now
+                + chrono::Duration::from_std(delay).unwrap_or_else(|_| chrono::Duration::zero());
+            info!(
+                "delivery to {} failed ({}), retrying in {:?}",
+                &delivery.inbox, reason, delay
+            );
+            state.store.save_delivery(&delivery)
+        }
+    }
+}
+
+async fn attempt(state: &State, config: &DeliveryConfig, delivery: &Delivery) -> Attempt {
+    let key = match state.keys.key_for_project(delivery.project_id).await {
+        Ok(key) => key,
+        Err(err) => return Attempt::Retry(format!("unable to load key: {:#}", err)),
+    };
+
+    let key_id = key_url(&delivery.actor);
+    let request =
+        state
+            .client
+            .post_activity(&delivery.inbox, delivery.activity.as_bytes(), &key_id, &key);
+    match tokio::time::timeout(config.timeout, request).await {
+        Err(_) => Attempt::Retry("timed out".to_string()),
+        Ok(Err(err)) => Attempt::Retry(format!("{:#}", err)),
+        Ok(Ok(status)) if status.is_success() => Attempt::Delivered,
+        Ok(Ok(status))
+            if status.is_server_error()
+                || status == StatusCode::TOO_MANY_REQUESTS
+                || status == StatusCode::REQUEST_TIMEOUT =>
+        {
+            Attempt::Retry(format!("inbox returned status {}", status))
+        }
+        Ok(Ok(status)) => Attempt::Fail(format!("inbox returned status {}", status)),
+    }
+}
@@ -1,3 +1,4 @@
+use crate::cohost::types::CohostError;
 use axum::response::IntoResponse;
 use hyper::StatusCode;
 use std::fmt::Display;
@@ -50,6 +51,20 @@ impl Display for ErrorWithStatus {
 
 impl std::error::Error for ErrorWithStatus {}
 
+impl From<CohostError> for ErrorWithStatus {
+    fn from(err: CohostError) -> Self {
+        let status = match err.data.http_status {
+            404 => StatusCode::NOT_FOUND,
+            403 => StatusCode::FORBIDDEN,
+            _ => StatusCode::BAD_GATEWAY,
+        };
+        Self {
+            status,
+            message: err.to_string(),
+        }
+    }
+}
+
 impl IntoResponse for ErrorWithStatus {
     fn into_response(self) -> axum::response::Response {
         (self.status, self.message).into_response()
@@ -0,0 +1,58 @@
+use super::{
+    activitystreams::{actor_url, Note, OrderedCollection},
+    error::{ErrorWithStatus, ResponseResult},
+    outbox::readable_posts,
+    post::bridged_share,
+    server::{activity_json, State},
+    user::fetch_project_by_id,
+};
+use crate::cohost::types::{ProfilePostsInput, ProfilePostsInputOptions};
+use anyhow::Context;
+use axum::{extract::Path, Extension, Json};
+use http::{HeaderMap, Method, Uri};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Collection of a project's pinned posts, which Mastodon shows at the top of a profile
+pub async fn handle_featured(
+    Path(project_id): Path<u64>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    state: Extension<Arc<State>>,
+) -> ResponseResult<(HeaderMap, Json<Value>)> {
+    let project = fetch_project_by_id(&state, project_id).await?;
+    // Cohost always lists pinned posts at the start of the first page
+    let posts = state
+        .api
+        .trpc_query_single(&ProfilePostsInput {
+            project_handle: project.handle.clone(),
+            page: 0,
+            options: ProfilePostsInputOptions {
+                hide_replies: false,
+                hide_shares: false,
+            },
+        })
+        .await?
+        .map_err(ErrorWithStatus::from)?;
+
+    let items = readable_posts(&state, posts.posts, &method, &uri, &headers)
+        .await?
+        .iter()
+        .filter(|post| post.pinned && post.transparent_share_of_post_id.is_none())
+        .map(|post| {
+            serde_json::to_value(Note::with_post(
+                &state.domain,
+                post,
+                &state.adult_content_warning,
+                bridged_share(&state, post)?,
+            ))
+            .context("unable to serialize note")
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    activity_json(&OrderedCollection::with_items(
+        format!("{}/featured", actor_url(&state.domain, project_id)),
+        items,
+    ))
+}
@@ -0,0 +1,107 @@
+use super::{
+    activitystreams::{actor_url, OrderedCollection},
+    error::ResponseResult,
+    server::{activity_json, State},
+    signature::verify_request,
+    user::fetch_project_by_id,
+};
+use crate::cohost::types::{Privacy, Project};
+use axum::{extract::Path, Extension, Json};
+use http::{HeaderMap, Method, Uri};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use tracing::debug;
+
+/// A remote actor following a bridged project
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Follower {
+    /// ID of the remote actor
+    pub actor: String,
+    pub inbox: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_inbox: Option<String>,
+    /// Handle in the actor ID they followed, if they followed an ID based on
+    /// the handle rather than the project ID and haven't been moved yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legacy_handle: Option<String>,
+}
+
+/// A follow of a private project, waiting to be approved
+#[derive(Debug, Clone)]
+pub struct FollowRequest {
+    pub follower: Follower,
+    /// The `Follow` activity, which an `Accept` has to refer to
+    pub activity: String,
+    pub received: chrono::DateTime<chrono::Utc>,
+}
+
+/// Server part of an actor ID
+fn actor_server(actor: &str) -> Option<String> {
+    actor.parse::<Uri>().ok().and_then(|uri| {
+        uri.authority()
+            .map(|authority| authority.as_str().to_lowercase())
+    })
+}
+
+/// Whether a request may see a project's posts. Posts of private projects
+/// are only shown to signed requests from a follower, or from an allowed
+/// instance actor of a server one of the followers is on, since some
+/// servers fetch as their instance actor
+pub async fn may_read_posts(
+    state: &State,
+    project: &Project,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> anyhow::Result<bool> {
+    if project.privacy == Privacy::Public {
+        return Ok(true);
+    }
+    if !headers.contains_key("signature") {
+        return Ok(false);
+    }
+    let key = match verify_request(
+        &state.client,
+        state.store.as_ref(),
+        method,
+        uri,
+        headers,
+        &[],
+        state.clock_skew,
+    )
+    .await
+    {
+        Ok(key) => key,
+        Err(err) => {
+            debug!("not showing private posts: {}", err.message);
+            return Ok(false);
+        }
+    };
+
+    let followers = state.store.followers(project.project_id)?;
+    if followers.iter().any(|follower| follower.actor == key.owner) {
+        return Ok(true);
+    }
+    if !state.instance_actors.contains(&key.owner) {
+        return Ok(false);
+    }
+    let signer_server = actor_server(&key.owner);
+    Ok(signer_server.is_some()
+        && followers
+            .iter()
+            .any(|follower| actor_server(&follower.actor) == signer_server))
+}
+
+pub async fn handle_followers(
+    Path(project_id): Path<u64>,
+    state: Extension<Arc<State>>,
+) -> ResponseResult<(HeaderMap, Json<Value>)> {
+    let project = fetch_project_by_id(&state, project_id).await?;
+    let followers = state.store.followers(project.project_id)?;
+    activity_json(&OrderedCollection::with_total_items(
+        format!("{}/followers", actor_url(&state.domain, project.project_id)),
+        followers.len() as u64,
+    ))
+}
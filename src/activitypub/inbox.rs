@@ -0,0 +1,220 @@
+use super::{
+    activitystreams::{local_actor_from_url, Activity, LocalActor},
+    error::{ErrorWithStatus, ResponseResult},
+    followers::{FollowRequest, Follower},
+    server::State,
+    signature::verify_request,
+    user::{fetch_project, fetch_project_by_id},
+};
+use crate::cohost::types::{Privacy, Project};
+use anyhow::Context;
+use axum::{body::Bytes, extract::Path, Extension};
+use http::{HeaderMap, Method, StatusCode, Uri};
+use serde_json::Value;
+use std::sync::Arc;
+use tracing::{debug, info};
+
+fn bad_request(message: &str) -> ErrorWithStatus {
+    ErrorWithStatus {
+        status: StatusCode::BAD_REQUEST,
+        message: message.to_string(),
+    }
+}
+
+/// Get the ID of an object which may be given either inline or as a link
+fn object_id(object: &Value) -> Option<&str> {
+    match object {
+        Value::String(id) => Some(id),
+        Value::Object(map) => map.get("id").and_then(Value::as_str),
+        _ => None,
+    }
+}
+
+/// Look up the project bridged by one of our actors
+async fn fetch_local_project(state: &State, actor: LocalActor<'_>) -> ResponseResult<Project> {
+    match actor {
+        LocalActor::Project(project_id) => fetch_project_by_id(state, project_id).await,
+        LocalActor::Handle(handle) => fetch_project(state, handle).await,
+    }
+}
+
+/// Inbox for a single bridged actor, under either its canonical ID or its handle
+pub async fn handle_inbox(
+    Path(user): Path<String>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+    Extension(state): Extension<Arc<State>>,
+) -> ResponseResult<StatusCode> {
+    receive(&state, Some(&user), &method, &uri, &headers, &body).await
+}
+
+/// Inbox shared between all bridged actors, advertised in each actor's `endpoints`
+pub async fn handle_shared_inbox(
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+    Extension(state): Extension<Arc<State>>,
+) -> ResponseResult<StatusCode> {
+    receive(&state, None, &method, &uri, &headers, &body).await
+}
+
+async fn receive(
+    state: &State,
+    user: Option<&str>,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> ResponseResult<StatusCode> {
+    let key = verify_request(
+        &state.client,
+        state.store.as_ref(),
+        method,
+        uri,
+        headers,
+        body,
+        state.clock_skew,
+    )
+    .await?;
+
+    let activity: Value =
+        serde_json::from_slice(body).map_err(|_| bad_request("activity is not valid JSON"))?;
+    let actor = activity
+        .get("actor")
+        .and_then(Value::as_str)
+        .ok_or_else(|| bad_request("activity has no actor"))?;
+    if actor != key.owner {
+        return Err(ErrorWithStatus {
+            status: StatusCode::UNAUTHORIZED,
+            message: "activity actor does not match signer".to_string(),
+        }
+        .into());
+    }
+
+    let activity_type = activity
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or("unknown");
+    info!(
+        "received {} from {} for {}",
+        activity_type,
+        actor,
+        user.unwrap_or("shared inbox")
+    );
+    debug!("activity: {}", &activity);
+
+    match activity_type {
+        "Follow" => handle_follow(state, actor, &activity).await?,
+        "Undo" => handle_undo(state, actor, &activity).await?,
+        _ => debug!("ignoring unsupported activity"),
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Record a new follower and send them an `Accept`. Follows of private
+/// projects are only recorded as requests, since the bridge can't know
+/// whether the project would approve them
+async fn handle_follow(state: &State, actor: &str, activity: &Value) -> ResponseResult<()> {
+    let target = activity
+        .get("object")
+        .and_then(object_id)
+        .ok_or_else(|| bad_request("follow has no object"))?;
+    let local_actor =
+        local_actor_from_url(&state.domain, target).ok_or_else(|| ErrorWithStatus {
+            status: StatusCode::NOT_FOUND,
+            message: "follow is not for a bridged actor".to_string(),
+        })?;
+    let project = fetch_local_project(state, local_actor).await?;
+
+    let remote = state
+        .client
+        .fetch_actor(state.store.as_ref(), actor)
+        .await
+        .context("failed to fetch follower")?;
+    let follower = Follower {
+        actor: actor.to_string(),
+        inbox: remote.inbox,
+        shared_inbox: remote.endpoints.shared_inbox,
+        // Servers which still know the actor by its old ID are moved later
+        legacy_handle: match local_actor {
+            LocalActor::Project(_) => None,
+            LocalActor::Handle(handle) => Some(handle.to_string()),
+        },
+    };
+
+    if project.privacy == Privacy::Private {
+        state.store.add_follow_request(
+            project.project_id,
+            &FollowRequest {
+                follower,
+                activity: activity.to_string(),
+                received: chrono::Utc::now(),
+            },
+        )?;
+        info!(
+            "{} asked to follow private project {}",
+            actor, &project.handle
+        );
+        return Ok(());
+    }
+
+    state.store.add_follower(project.project_id, &follower)?;
+    info!("{} now follows {}", actor, &project.handle);
+
+    // Accept as the actor they followed, which is the one they know
+    let mut accept = Activity::accept(target.to_string(), activity.clone());
+    accept.to = vec![actor.to_string()];
+    state
+        .delivery
+        .deliver(project.project_id, target, &follower.inbox, &accept)?;
+
+    Ok(())
+}
+
+/// Handle `Undo`, which we only support for follows
+async fn handle_undo(state: &State, actor: &str, activity: &Value) -> ResponseResult<()> {
+    let object = activity
+        .get("object")
+        .ok_or_else(|| bad_request("undo has no object"))?;
+    if object.get("type").and_then(Value::as_str) != Some("Follow") {
+        debug!("ignoring undo of something other than a follow");
+        return Ok(());
+    }
+    let target = object
+        .get("object")
+        .and_then(object_id)
+        .ok_or_else(|| bad_request("follow has no object"))?;
+    let local_actor = match local_actor_from_url(&state.domain, target) {
+        Some(local_actor) => local_actor,
+        None => return Ok(()),
+    };
+    let project = fetch_local_project(state, local_actor).await?;
+
+    // Servers handle a `Move` by following the new ID and then undoing the
+    // follow of the old one, which mustn't remove the new follow
+    let moved = matches!(local_actor, LocalActor::Handle(_))
+        && state
+            .store
+            .followers(project.project_id)?
+            .iter()
+            .any(|follower| follower.actor == actor && follower.legacy_handle.is_none());
+    if moved {
+        debug!(
+            "ignoring undo of {}'s follow from before it was moved",
+            actor
+        );
+    } else if state.store.remove_follower(project.project_id, actor)? {
+        info!("{} no longer follows {}", actor, &project.handle);
+    }
+    if state
+        .store
+        .remove_follow_request(project.project_id, actor)?
+    {
+        info!("{} withdrew a request to follow {}", actor, &project.handle);
+    }
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use crate::store::Store;
+use anyhow::Context;
+use rsa::pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::RsaPrivateKey;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::info;
+
+/// Size of generated keys. 2048 is what Mastodon uses
+const KEY_BITS: usize = 2048;
+
+/// Signing keys for bridged actors.
+/// Keys are stored by the cohost `project_id`, so a project keeps its key
+/// when its handle changes.
+pub struct KeyStore {
+    store: Arc<dyn Store>,
+    cache: Mutex<HashMap<u64, Arc<RsaPrivateKey>>>,
+}
+
+impl KeyStore {
+    pub fn new(store: Arc<dyn Store>) -> Self {
+        Self {
+            store,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the key for a project, generating and saving one if this is the
+    /// first time the project has been bridged
+    pub async fn key_for_project(&self, project_id: u64) -> anyhow::Result<Arc<RsaPrivateKey>> {
+        // Held for the whole function so concurrent requests can't generate two keys
+        let mut cache = self.cache.lock().await;
+        if let Some(key) = cache.get(&project_id) {
+            return Ok(key.clone());
+        }
+
+        let key = match self.store.key(project_id)? {
+            Some(pem) => RsaPrivateKey::from_pkcs8_pem(&pem)
+                .with_context(|| format!("failed to parse key for project {}", project_id))?,
+            None => {
+                info!("generating key for project {}", project_id);
+                let key = tokio::task::spawn_blocking(|| {
+                    RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS)
+                })
+                .await
+                .context("key generation task failed")?
+                .context("failed to generate key")?;
+                let pem = key
+                    .to_pkcs8_pem(LineEnding::LF)
+                    .context("failed to encode key")?;
+                self.store.set_key(project_id, &pem)?;
+                key
+            }
+        };
+
+        let key = Arc::new(key);
+        cache.insert(project_id, key.clone());
+        Ok(key)
+    }
+}
+
+/// Encode the public half of a key as SPKI PEM, the form used in `publicKeyPem`
+pub fn public_key_pem(key: &RsaPrivateKey) -> anyhow::Result<String> {
+    key.to_public_key()
+        .to_public_key_pem(LineEnding::LF)
+        .context("failed to encode public key")
+}
@@ -1,5 +1,18 @@
 pub mod activitystreams;
+pub mod client;
+pub mod delivery;
 pub mod error;
+pub mod featured;
+pub mod followers;
+pub mod inbox;
+pub mod keys;
+pub mod negotiate;
+pub mod nodeinfo;
+pub mod outbox;
+pub mod post;
+pub mod render;
 pub mod server;
+pub mod signature;
+pub mod tags;
 pub mod user;
 pub mod webfinger;
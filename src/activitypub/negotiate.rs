@@ -0,0 +1,93 @@
+//! Content negotiation for URLs which are both ActivityPub objects and
+//! links people may click on. Fediverse software asks for JSON-LD, while
+//! browsers are sent to the original page on cohost.
+
+use super::{post::fetch_post, server::State};
+use axum::{
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{header, HeaderMap, HeaderValue, Request, StatusCode};
+use std::sync::Arc;
+
+/// Whether an `Accept` header asks for ActivityStreams JSON
+pub fn wants_activity_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_range| {
+            let mut parts = media_range.split(';').map(str::trim);
+            let media_type = parts.next().unwrap_or_default().to_ascii_lowercase();
+            match media_type.as_str() {
+                "application/activity+json" => true,
+                "application/ld+json" => parts.any(|param| {
+                    param
+                        .split_once('=')
+                        .map(|(name, value)| {
+                            name.trim().eq_ignore_ascii_case("profile")
+                                && value.trim().trim_matches('"')
+                                    == "https://www.w3.org/ns/activitystreams"
+                        })
+                        .unwrap_or(false)
+                }),
+                _ => false,
+            }
+        })
+}
+
+/// The page on cohost corresponding to one of our paths. Paths with a
+/// project ID need `state` to know the project's handle, and posts to know
+/// their page
+async fn cohost_url(state: Option<&State>, path: &str) -> Option<String> {
+    let mut segments = path.strip_prefix('/')?.split('/');
+    let handle = match segments.next()? {
+        "users" => segments.next()?.to_string(),
+        "projects" => {
+            let project_id = segments.next()?.parse().ok()?;
+            state?.store.project_handle(project_id).ok()??
+        }
+        _ => return None,
+    };
+    match (segments.next(), segments.next(), segments.next()) {
+        (None, _, _) => Some(format!("https://cohost.org/{}", handle)),
+        (Some("posts"), Some(post_id), None) => {
+            post_page_url(state?, &handle, post_id.parse().ok()?).await
+        }
+        _ => None,
+    }
+}
+
+/// Cohost's page for a post. Its URL has a slug of the headline, so it
+/// comes from cohost rather than being made up
+async fn post_page_url(state: &State, handle: &str, post_id: u64) -> Option<String> {
+    if let Some(bridged) = state.store.bridged_post(post_id).ok()? {
+        if !bridged.url.is_empty() {
+            return Some(bridged.url);
+        }
+    }
+    // Posts which weren't bridged, or were bridged before URLs were kept
+    fetch_post(state, handle, post_id)
+        .await
+        .ok()?
+        .map(|post| post.single_post_page_url)
+}
+
+/// Middleware which redirects anything not asking for ActivityStreams to cohost
+pub async fn redirect_browsers<B>(request: Request<B>, next: Next<B>) -> Response {
+    let vary = (header::VARY, HeaderValue::from_static("Accept"));
+
+    if !wants_activity_json(request.headers()) {
+        let state = request.extensions().get::<Arc<State>>().map(Arc::as_ref);
+        if let Some(url) = cohost_url(state, request.uri().path()).await {
+            if let Ok(location) = HeaderValue::from_str(&url) {
+                return (StatusCode::FOUND, [(header::LOCATION, location), vary]).into_response();
+            }
+        }
+    }
+
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(vary.0, vary.1);
+    response
+}
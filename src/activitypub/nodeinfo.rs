@@ -0,0 +1,122 @@
+//! NodeInfo, used by crawlers and admin tools to identify the server.
+//! See <https://nodeinfo.diaspora.software/protocol>
+
+use super::{error::ResponseResult, server::State};
+use crate::cohost::api::VERSION;
+use axum::{Extension, Json};
+use http::{header, HeaderMap};
+use serde::Serialize;
+use std::sync::Arc;
+
+const REPOSITORY: &str = env!("CARGO_PKG_REPOSITORY");
+
+#[derive(Serialize, Debug, Clone)]
+pub struct NodeInfoLinks {
+    pub links: Vec<NodeInfoLink>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct NodeInfoLink {
+    pub rel: String,
+    pub href: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeInfo {
+    pub version: String,
+    pub software: Software,
+    pub protocols: Vec<String>,
+    pub services: Services,
+    pub open_registrations: bool,
+    pub usage: Usage,
+    pub metadata: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Software {
+    pub name: String,
+    pub version: String,
+    /// Only allowed in NodeInfo 2.1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Services {
+    pub inbound: Vec<String>,
+    pub outbound: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    pub users: Users,
+    pub local_posts: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Users {
+    pub total: u64,
+}
+
+fn schema(version: &str) -> String {
+    format!("http://nodeinfo.diaspora.software/ns/schema/{}", version)
+}
+
+pub async fn handle_nodeinfo_links(state: Extension<Arc<State>>) -> Json<NodeInfoLinks> {
+    Json(NodeInfoLinks {
+        links: ["2.0", "2.1"]
+            .iter()
+            .map(|version| NodeInfoLink {
+                rel: schema(version),
+                href: format!("https://{}/nodeinfo/{}", &state.domain, version),
+            })
+            .collect(),
+    })
+}
+
+fn nodeinfo(state: &State, version: &str) -> ResponseResult<(HeaderMap, Json<NodeInfo>)> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        format!(r#"application/json; profile="{}#""#, schema(version))
+            .parse()
+            .unwrap(),
+    );
+
+    Ok((
+        headers,
+        Json(NodeInfo {
+            version: version.to_string(),
+            software: Software {
+                name: "cobridge".to_string(),
+                version: VERSION.to_string(),
+                repository: (version != "2.0" && !REPOSITORY.is_empty())
+                    .then(|| REPOSITORY.to_string()),
+            },
+            protocols: vec!["activitypub".to_string()],
+            services: Services::default(),
+            open_registrations: false,
+            usage: Usage {
+                users: Users {
+                    total: state.store.count_projects()?,
+                },
+                local_posts: state.store.count_posts()?,
+            },
+            metadata: serde_json::Map::new(),
+        }),
+    ))
+}
+
+pub async fn handle_nodeinfo_2_0(
+    state: Extension<Arc<State>>,
+) -> ResponseResult<(HeaderMap, Json<NodeInfo>)> {
+    nodeinfo(&state, "2.0")
+}
+
+pub async fn handle_nodeinfo_2_1(
+    state: Extension<Arc<State>>,
+) -> ResponseResult<(HeaderMap, Json<NodeInfo>)> {
+    nodeinfo(&state, "2.1")
+}
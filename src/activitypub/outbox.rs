@@ -0,0 +1,99 @@
+use super::{
+    activitystreams::{actor_url, OrderedCollection, OrderedCollectionPage, PostActivity},
+    error::{ErrorWithStatus, ResponseResult},
+    followers::may_read_posts,
+    post::bridged_share,
+    server::{activity_json, State},
+    user::fetch_project_by_id,
+};
+use crate::cohost::types::{Post, Privacy, ProfilePostsInput, ProfilePostsInputOptions};
+use axum::{
+    extract::{Path, Query},
+    Extension, Json,
+};
+use http::{HeaderMap, Method, Uri};
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+pub struct OutboxQuery {
+    /// Page of the outbox, matching cohost's page numbers. If absent the
+    /// collection itself is returned rather than a page
+    pub page: Option<u64>,
+}
+
+/// Remove posts by projects which haven't agreed to be bridged, and by
+/// private projects unless the request comes from a follower
+pub async fn readable_posts(
+    state: &State,
+    posts: Vec<Post>,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> anyhow::Result<Vec<Post>> {
+    let posts: Vec<Post> = posts
+        .into_iter()
+        .filter(|post| state.consent.allows(&post.posting_project))
+        .collect();
+    let private_project = posts
+        .iter()
+        .map(|post| &post.posting_project)
+        .find(|project| project.privacy == Privacy::Private);
+    match private_project {
+        Some(project) if !may_read_posts(state, project, method, uri, headers).await? => Ok(posts
+            .into_iter()
+            .filter(|post| post.posting_project.privacy == Privacy::Public)
+            .collect()),
+        _ => Ok(posts),
+    }
+}
+
+pub async fn handle_outbox(
+    Path(project_id): Path<u64>,
+    query: Query<OutboxQuery>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    state: Extension<Arc<State>>,
+) -> ResponseResult<(HeaderMap, Json<Value>)> {
+    let project = fetch_project_by_id(&state, project_id).await?;
+    let outbox_id = format!("{}/outbox", actor_url(&state.domain, project_id));
+    // The collection itself only links to the first page, so cohost isn't asked
+    let page = match query.page {
+        Some(page) => page,
+        None => return activity_json(&OrderedCollection::paged(outbox_id, None)),
+    };
+
+    let posts = state
+        .api
+        .trpc_query_single(&ProfilePostsInput {
+            project_handle: project.handle.clone(),
+            page,
+            options: ProfilePostsInputOptions {
+                hide_replies: false,
+                hide_shares: false,
+            },
+        })
+        .await?
+        .map_err(ErrorWithStatus::from)?;
+
+    let items = readable_posts(&state, posts.posts, &method, &uri, &headers)
+        .await?
+        .iter()
+        .map(|post| {
+            Ok(PostActivity::with_post(
+                &state.domain,
+                post,
+                &state.adult_content_warning,
+                bridged_share(&state, post)?,
+            ))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    activity_json(&OrderedCollectionPage::with_pagination(
+        outbox_id,
+        &posts.pagination,
+        items,
+    ))
+}
@@ -0,0 +1,103 @@
+use super::{
+    activitystreams::{post_url, Note, Tombstone},
+    error::{ErrorWithStatus, ResponseResult},
+    followers::may_read_posts,
+    server::{activity_json, State},
+};
+use crate::cohost::types::{self, Post};
+use anyhow::Context;
+use axum::{extract::Path, Extension, Json};
+use http::{HeaderMap, Method, StatusCode, Uri};
+use serde_json::Value;
+use std::sync::Arc;
+
+pub async fn handle_post(
+    Path((project_id, post_id)): Path<(u64, u64)>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    state: Extension<Arc<State>>,
+) -> ResponseResult<(StatusCode, HeaderMap, Json<Value>)> {
+    let not_found = || ErrorWithStatus {
+        status: StatusCode::NOT_FOUND,
+        message: "no such post".to_string(),
+    };
+    // Posts the poller hasn't recorded still exist here, since the outbox
+    // and featured collections link to every post on cohost
+    let deleted = state
+        .store
+        .bridged_post(post_id)?
+        .filter(|bridged| bridged.project_id == project_id)
+        .and_then(|bridged| bridged.deleted);
+    if let Some(deleted) = deleted {
+        return gone(&state, project_id, post_id, deleted);
+    }
+    let handle = state
+        .store
+        .project_handle(project_id)?
+        .ok_or_else(not_found)?;
+
+    match fetch_post(&state, &handle, post_id).await? {
+        Some(post) => {
+            // Posts which can't be bridged are hidden as if they don't exist
+            if post.post_id != post_id
+                || post.posting_project.project_id != project_id
+                || !state.consent.allows(&post.posting_project)
+                || !may_read_posts(&state, &post.posting_project, &method, &uri, &headers).await?
+            {
+                return Err(not_found().into());
+            }
+            let (headers, json) = activity_json(&Note::with_post(
+                &state.domain,
+                &post,
+                &state.adult_content_warning,
+                bridged_share(&state, &post)?,
+            ))?;
+            Ok((StatusCode::OK, headers, json))
+        }
+        // Only the poller decides a post was deleted, once it has been
+        // missing for more than one poll
+        None => Err(not_found().into()),
+    }
+}
+
+fn gone(
+    state: &State,
+    project_id: u64,
+    post_id: u64,
+    deleted: chrono::DateTime<chrono::Utc>,
+) -> ResponseResult<(StatusCode, HeaderMap, Json<Value>)> {
+    let tombstone = Tombstone::note(post_url(&state.domain, project_id, post_id), Some(deleted));
+    let (headers, json) = activity_json(&tombstone)?;
+    Ok((StatusCode::GONE, headers, json))
+}
+
+/// The post a post shares, if it was bridged too so its ID can be fetched
+pub fn bridged_share<'a>(state: &State, post: &'a Post) -> anyhow::Result<Option<&'a Post>> {
+    let shared = match post.shared_post() {
+        Some(shared) => shared,
+        None => return Ok(None),
+    };
+    let bridged = state
+        .store
+        .bridged_post(shared.post_id)?
+        .is_some_and(|bridged| bridged.deleted.is_none());
+    Ok((bridged && state.consent.allows(&shared.posting_project)).then_some(shared))
+}
+
+/// Look up a post through its page on cohost, or `None` if cohost says it
+/// doesn't exist. Any other error loading the page is an error, since the
+/// post may well still exist
+pub async fn fetch_post(state: &State, handle: &str, post_id: u64) -> anyhow::Result<Option<Post>> {
+    let response_value = state
+        .api
+        .query_loader_state(&format!("/{}/post/{}", handle, post_id))
+        .await?;
+    match serde_json::from_value::<types::SinglePostViewLoaderState>(response_value)
+        .context("failed to parse cohost response")?
+    {
+        types::SinglePostViewLoaderState::SinglePostView(view) => Ok(Some(view.post)),
+        types::SinglePostViewLoaderState::Error(err) if err.is_not_found() => Ok(None),
+        types::SinglePostViewLoaderState::Error(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,170 @@
+use crate::cohost::types::{AskBlock, Block};
+use ammonia::UrlRelative;
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+/// Tags allowed in Note content, matching Mastodon's `MASTODON_STRICT`
+/// sanitizer config. Anything else is stripped but its text is kept
+const ALLOWED_TAGS: &[&str] = &[
+    "p",
+    "br",
+    "span",
+    "a",
+    "abbr",
+    "del",
+    "s",
+    "pre",
+    "blockquote",
+    "code",
+    "b",
+    "strong",
+    "u",
+    "sub",
+    "sup",
+    "i",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "ul",
+    "ol",
+    "li",
+    "ruby",
+    "rt",
+    "rp",
+];
+
+/// URL schemes Mastodon keeps in links
+const ALLOWED_SCHEMES: &[&str] = &[
+    "http", "https", "dat", "dweb", "ipfs", "ipns", "ssb", "gopher", "xmpp", "magnet", "gemini",
+];
+
+fn sanitizer() -> &'static ammonia::Builder<'static> {
+    static SANITIZER: OnceLock<ammonia::Builder<'static>> = OnceLock::new();
+    SANITIZER.get_or_init(|| {
+        let mut builder = ammonia::Builder::empty();
+        builder
+            .tags(ALLOWED_TAGS.iter().copied().collect())
+            .tag_attributes(HashMap::from([
+                ("a", HashSet::from(["href"])),
+                ("abbr", HashSet::from(["title"])),
+                ("ol", HashSet::from(["start", "reversed"])),
+                ("li", HashSet::from(["value"])),
+            ]))
+            .generic_attributes(HashSet::new())
+            .url_schemes(ALLOWED_SCHEMES.iter().copied().collect())
+            .url_relative(UrlRelative::Deny)
+            .link_rel(Some("nofollow noopener noreferrer"))
+            .clean_content_tags(HashSet::from(["script", "style"]));
+        builder
+    })
+}
+
+/// Escape text so it can be included in HTML content
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Render a link to a URL given by a project, or `None` if its scheme
+/// isn't one which is kept in links. The text of the link is the URL
+pub fn render_link(url: &str) -> Option<String> {
+    let (scheme, _) = url.split_once(':')?;
+    let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    if !valid_scheme || !ALLOWED_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()) {
+        return None;
+    }
+    let url = escape_html(url);
+    Some(format!(
+        r#"<a href="{}" rel="me nofollow noopener noreferrer" target="_blank">{}</a>"#,
+        url, url
+    ))
+}
+
+/// Render cohost markdown to HTML without sanitizing it.
+/// Cohost uses GitHub-flavored markdown with inline HTML, and treats every
+/// newline inside a paragraph as a line break.
+fn render_markdown_unsanitized(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_TASKLISTS;
+    let parser = Parser::new_ext(markdown, options).map(|event| match event {
+        Event::SoftBreak => Event::HardBreak,
+        // Inline images can't be shown in content, so link to them instead
+        Event::Start(Tag::Image(link_type, url, title)) => {
+            Event::Start(Tag::Link(link_type, url, title))
+        }
+        Event::End(Tag::Image(link_type, url, title)) => {
+            Event::End(Tag::Link(link_type, url, title))
+        }
+        event => event,
+    });
+
+    let mut output = String::new();
+    html::push_html(&mut output, parser);
+    output
+}
+
+/// Render cohost markdown into HTML that fediverse servers will accept
+pub fn render_markdown(markdown: &str) -> String {
+    sanitizer()
+        .clean(&render_markdown_unsanitized(markdown))
+        .to_string()
+}
+
+/// Render an ask as a quote of the question, saying who asked it
+fn render_ask_unsanitized(ask: &AskBlock) -> String {
+    let asker = match (&ask.asking_project, ask.anon) {
+        (Some(project), false) => format!(
+            "<a href=\"https://cohost.org/{}\">@{}</a>",
+            urlencoding::encode(&project.handle),
+            escape_html(&project.handle)
+        ),
+        _ => "Anonymous".to_string(),
+    };
+    format!(
+        "<blockquote><p>{} asked:</p>{}</blockquote>",
+        asker,
+        render_markdown_unsanitized(&ask.content)
+    )
+}
+
+/// Render the blocks of a post into HTML suitable for a Note's content.
+/// Attachments are not included, they are sent separately
+pub fn render_blocks(blocks: &[Block]) -> String {
+    let mut output = String::new();
+    // Consecutive markdown blocks are rendered together, since cohost
+    // allows markdown constructs such as lists to continue between them
+    let mut markdown = Vec::new();
+    for block in blocks {
+        match block {
+            Block::Markdown { markdown: block } => markdown.push(block.content.as_str()),
+            Block::Ask { ask } => {
+                output.push_str(&render_markdown_unsanitized(&markdown.join("\n\n")));
+                markdown.clear();
+                output.push_str(&render_ask_unsanitized(ask));
+            }
+            Block::Attachment { .. } => {}
+        }
+    }
+    output.push_str(&render_markdown_unsanitized(&markdown.join("\n\n")));
+    sanitizer().clean(&output).to_string()
+}
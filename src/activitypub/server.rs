@@ -1,11 +1,34 @@
+use super::client::FederationClient;
+use super::delivery::DeliveryQueue;
+use super::error::ResponseResult;
+use super::keys::KeyStore;
 use crate::cohost::CohostApi;
+use crate::consent::ConsentPolicy;
+use crate::store::Store;
+use anyhow::Context;
 use axum::Json;
+use http::{header, HeaderMap};
 use hyper::StatusCode;
+use serde::Serialize;
 use serde_json::{json, Value};
+use std::sync::Arc;
 
 pub struct State {
+    pub store: Arc<dyn Store>,
     pub api: CohostApi,
     pub domain: String,
+    pub keys: KeyStore,
+    pub client: FederationClient,
+    pub delivery: DeliveryQueue,
+    /// Which projects may be bridged
+    pub consent: ConsentPolicy,
+    /// Content warning for adult content posted without one
+    pub adult_content_warning: String,
+    /// How far the `Date` of a signed request may be from our clock
+    pub clock_skew: chrono::Duration,
+    /// Instance actors of other servers, which may see private posts for
+    /// followers on their server
+    pub instance_actors: Vec<String>,
 }
 
 pub async fn json_error(err: anyhow::Error) -> (StatusCode, Json<Value>) {
@@ -16,3 +39,17 @@ pub async fn json_error(err: anyhow::Error) -> (StatusCode, Json<Value>) {
         })),
     )
 }
+
+/// Serialize an ActivityStreams object into a response with the
+/// `application/activity+json` content type
+pub fn activity_json<T: Serialize>(object: &T) -> ResponseResult<(HeaderMap, Json<Value>)> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        "application/activity+json; charset=utf-8".parse().unwrap(),
+    );
+    Ok((
+        headers,
+        Json(serde_json::to_value(object).context("unable to serialize object")?),
+    ))
+}
@@ -0,0 +1,319 @@
+//! HTTP Signatures, as described in draft-cavage-http-signatures-12,
+//! which is what Mastodon and most other servers use to authenticate requests.
+
+use super::{client::FederationClient, error::ErrorWithStatus};
+use crate::store::Store;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use http::{header, HeaderMap, Method, StatusCode, Uri};
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs1v15::{Signature, SigningKey, VerifyingKey};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::{SignatureEncoding, Signer, Verifier};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+use tracing::debug;
+
+fn unauthorized(message: impl Into<String>) -> ErrorWithStatus {
+    ErrorWithStatus {
+        status: StatusCode::UNAUTHORIZED,
+        message: message.into(),
+    }
+}
+
+fn bad_request(message: impl Into<String>) -> ErrorWithStatus {
+    ErrorWithStatus {
+        status: StatusCode::BAD_REQUEST,
+        message: message.into(),
+    }
+}
+
+/// Parsed contents of a `Signature` header
+#[derive(Debug, Clone)]
+pub struct SignatureHeader {
+    pub key_id: String,
+    pub algorithm: Option<String>,
+    /// Lowercase names of the signed headers, in order
+    pub headers: Vec<String>,
+    pub signature: Vec<u8>,
+    pub created: Option<i64>,
+    pub expires: Option<i64>,
+}
+
+impl FromStr for SignatureHeader {
+    type Err = ErrorWithStatus;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut key_id = None;
+        let mut algorithm = None;
+        let mut headers = None;
+        let mut signature = None;
+        let mut created = None;
+        let mut expires = None;
+
+        let mut rest = value.trim();
+        while !rest.is_empty() {
+            let (name, after_name) = rest
+                .split_once('=')
+                .ok_or_else(|| bad_request("malformed signature header"))?;
+            let name = name.trim();
+            let after_name = after_name.trim_start();
+
+            // Values are quoted strings, except for the numeric created and expires
+            let (param, after_param) = if let Some(quoted) = after_name.strip_prefix('"') {
+                quoted
+                    .split_once('"')
+                    .ok_or_else(|| bad_request("unterminated signature parameter"))?
+            } else {
+                after_name.split_once(',').unwrap_or((after_name, ""))
+            };
+
+            match name {
+                "keyId" => key_id = Some(param.to_string()),
+                "algorithm" => algorithm = Some(param.to_string()),
+                "headers" => {
+                    headers = Some(param.split_whitespace().map(str::to_lowercase).collect())
+                }
+                "signature" => {
+                    signature = Some(
+                        BASE64
+                            .decode(param)
+                            .map_err(|_| bad_request("signature is not valid base64"))?,
+                    )
+                }
+                "created" => created = param.trim().parse().ok(),
+                "expires" => expires = param.trim().parse().ok(),
+                _ => (),
+            }
+
+            rest = after_param
+                .trim_start()
+                .trim_start_matches(',')
+                .trim_start();
+        }
+
+        Ok(Self {
+            key_id: key_id.ok_or_else(|| bad_request("signature has no keyId"))?,
+            algorithm,
+            // The spec says only Date is signed if no headers are listed
+            headers: headers.unwrap_or_else(|| vec!["date".to_string()]),
+            signature: signature.ok_or_else(|| bad_request("signature has no signature"))?,
+            created,
+            expires,
+        })
+    }
+}
+
+impl SignatureHeader {
+    /// Build the string which was signed by the sender
+    pub fn signing_string(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+    ) -> Result<String, ErrorWithStatus> {
+        let mut lines = Vec::with_capacity(self.headers.len());
+        for name in &self.headers {
+            let value = match name.as_str() {
+                "(request-target)" => format!(
+                    "{} {}",
+                    method.as_str().to_lowercase(),
+                    uri.path_and_query()
+                        .map(|path| path.as_str())
+                        .unwrap_or_else(|| uri.path())
+                ),
+                "(created)" => self
+                    .created
+                    .ok_or_else(|| bad_request("(created) signed but not given"))?
+                    .to_string(),
+                "(expires)" => self
+                    .expires
+                    .ok_or_else(|| bad_request("(expires) signed but not given"))?
+                    .to_string(),
+                _ => {
+                    let values = headers
+                        .get_all(name.as_str())
+                        .iter()
+                        .map(|value| value.to_str().map(str::trim))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|_| bad_request(format!("header {} is not ASCII", name)))?;
+                    if values.is_empty() {
+                        return Err(bad_request(format!("signed header {} is missing", name)));
+                    }
+                    values.join(", ")
+                }
+            };
+            lines.push(format!("{}: {}", name, value));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    /// Check the signature against the sender's public key
+    pub fn verify(
+        &self,
+        signing_string: &str,
+        public_key_pem: &str,
+    ) -> Result<(), ErrorWithStatus> {
+        match self.algorithm.as_deref() {
+            None | Some("rsa-sha256") | Some("hs2019") => (),
+            Some(other) => {
+                return Err(unauthorized(format!(
+                    "unsupported signature algorithm {}",
+                    other
+                )))
+            }
+        }
+
+        let public_key = RsaPublicKey::from_public_key_pem(public_key_pem)
+            .or_else(|_| RsaPublicKey::from_pkcs1_pem(public_key_pem))
+            .map_err(|_| unauthorized("sender's public key is not a valid RSA key"))?;
+        let signature = Signature::try_from(self.signature.as_slice())
+            .map_err(|_| unauthorized("malformed signature"))?;
+
+        VerifyingKey::<Sha256>::new(public_key)
+            .verify(signing_string.as_bytes(), &signature)
+            .map_err(|_| unauthorized("signature does not match"))
+    }
+}
+
+/// Compute the value of a `Digest` header for a body
+pub fn digest_header(body: &[u8]) -> String {
+    format!("SHA-256={}", BASE64.encode(Sha256::digest(body)))
+}
+
+/// Check the `Digest` header matches the body
+pub fn verify_digest(headers: &HeaderMap, body: &[u8]) -> Result<(), ErrorWithStatus> {
+    let digest = headers
+        .get("digest")
+        .ok_or_else(|| unauthorized("request has no digest"))?
+        .to_str()
+        .map_err(|_| bad_request("digest is not ASCII"))?;
+
+    let expected = BASE64.encode(Sha256::digest(body));
+    let mut found_sha256 = false;
+    for entry in digest.split(',') {
+        if let Some((algorithm, value)) = entry.trim().split_once('=') {
+            if algorithm.eq_ignore_ascii_case("sha-256") {
+                found_sha256 = true;
+                if value == expected {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    if found_sha256 {
+        Err(unauthorized("digest does not match body"))
+    } else {
+        Err(bad_request("digest does not use SHA-256"))
+    }
+}
+
+/// Check the `Date` header is within `max_skew` of the current time
+pub fn verify_date(headers: &HeaderMap, max_skew: chrono::Duration) -> Result<(), ErrorWithStatus> {
+    let date = headers
+        .get(header::DATE)
+        .ok_or_else(|| unauthorized("request has no date"))?
+        .to_str()
+        .map_err(|_| bad_request("date is not ASCII"))?;
+    let date = chrono::DateTime::parse_from_rfc2822(date)
+        .map_err(|_| bad_request("date is not a valid HTTP date"))?;
+
+    let skew = chrono::Utc::now().signed_duration_since(date);
+    if skew > max_skew || -skew > max_skew {
+        return Err(unauthorized("request date is outside the allowed window"));
+    }
+    Ok(())
+}
+
+/// Verify the signature on a request from another server.
+/// `Date`, `Host` and `(request-target)` must all be signed so the request
+/// can't be replayed or altered, as must `Digest` for anything but a `GET`.
+/// Keys are cached, so a key which doesn't match is fetched again in case
+/// it has been rotated. Returns the key which signed the request
+pub async fn verify_request(
+    client: &FederationClient,
+    store: &dyn Store,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    body: &[u8],
+    max_skew: chrono::Duration,
+) -> Result<super::client::RemoteKey, ErrorWithStatus> {
+    let signature: SignatureHeader = headers
+        .get("signature")
+        .ok_or_else(|| unauthorized("request is not signed"))?
+        .to_str()
+        .map_err(|_| bad_request("signature is not ASCII"))?
+        .parse()?;
+
+    let has_body = method != Method::GET && method != Method::HEAD;
+    let mut required = vec!["(request-target)", "host", "date"];
+    if has_body {
+        required.push("digest");
+    }
+    for required in required {
+        if !signature.headers.iter().any(|name| name == required) {
+            return Err(unauthorized(format!("{} is not signed", required)));
+        }
+    }
+
+    if has_body {
+        verify_digest(headers, body)?;
+    }
+    verify_date(headers, max_skew)?;
+
+    let signing_string = signature.signing_string(method, uri, headers)?;
+    let mut refresh = false;
+    loop {
+        let key = client
+            .fetch_public_key(store, &signature.key_id, refresh)
+            .await
+            .map_err(|err| {
+                debug!("failed to fetch key {}: {:#}", &signature.key_id, err);
+                unauthorized("unable to fetch sender's public key")
+            })?;
+        match signature.verify(&signing_string, &key.public_key_pem) {
+            Ok(()) => return Ok(key),
+            Err(err) if refresh => return Err(err),
+            Err(_) => refresh = true,
+        }
+    }
+}
+
+/// Format a time as an HTTP date, for the `Date` header
+pub fn http_date(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Create a `Signature` header value for an outgoing request.
+/// `headers` are the (lowercase) names and values of the headers to sign,
+/// in addition to `(request-target)`
+pub fn sign_request(
+    key_id: &str,
+    key: &RsaPrivateKey,
+    method: &Method,
+    uri: &Uri,
+    headers: &[(&str, &str)],
+) -> String {
+    let mut names = vec!["(request-target)"];
+    let mut lines = vec![format!(
+        "(request-target): {} {}",
+        method.as_str().to_lowercase(),
+        uri.path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or_else(|| uri.path())
+    )];
+    for (name, value) in headers {
+        names.push(name);
+        lines.push(format!("{}: {}", name, value));
+    }
+
+    let signature = SigningKey::<Sha256>::new(key.clone()).sign(lines.join("\n").as_bytes());
+    format!(
+        r#"keyId="{}",algorithm="rsa-sha256",headers="{}",signature="{}""#,
+        key_id,
+        names.join(" "),
+        BASE64.encode(signature.to_bytes())
+    )
+}
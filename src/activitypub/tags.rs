@@ -0,0 +1,164 @@
+use super::{
+    activitystreams::{post_url, OrderedCollection, OrderedCollectionPage},
+    error::{ErrorWithStatus, ResponseResult},
+    followers::may_read_posts,
+    render::escape_html,
+    server::{activity_json, State},
+    user::fetch_project_by_id,
+};
+use crate::cohost::types::Pagination;
+use crate::store::BridgedPost;
+use axum::{
+    extract::{Path, Query},
+    Extension, Json,
+};
+use http::{HeaderMap, Method, StatusCode, Uri};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Number of posts in each page of a tag collection
+const PAGE_SIZE: u64 = 20;
+
+/// Turn a cohost tag, which may contain spaces and punctuation, into a
+/// fediverse hashtag. Multi-word tags become CamelCase, so
+/// `the cohost global feed` becomes `TheCohostGlobalFeed`.
+/// Returns `None` if nothing usable is left, since hashtags need at least one letter
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let words = tag
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    if !words
+        .iter()
+        .any(|word| word.chars().any(char::is_alphabetic))
+    {
+        return None;
+    }
+
+    if words.len() == 1 {
+        return Some(words[0].to_string());
+    }
+    Some(
+        words
+            .iter()
+            .map(|word| {
+                let mut chars = word.chars();
+                let first = chars.next().unwrap();
+                first.to_uppercase().chain(chars).collect::<String>()
+            })
+            .collect(),
+    )
+}
+
+/// URL of the collection of bridged posts with a hashtag
+pub fn tag_url(domain: &str, hashtag: &str) -> String {
+    format!("https://{}/tags/{}", domain, urlencoding::encode(hashtag))
+}
+
+/// HTML linking to each hashtag, shown with the tag's original text
+pub fn render_tags(domain: &str, tags: &[String]) -> String {
+    let links = tags
+        .iter()
+        .filter_map(|tag| {
+            let hashtag = normalize_tag(tag)?;
+            Some(format!(
+                r#"<a href="{}" class="mention hashtag" rel="tag">#<span>{}</span></a>"#,
+                escape_html(&tag_url(domain, &hashtag)),
+                // The link already starts with a `#`, so don't repeat the tag's own
+                escape_html(tag.trim_start_matches('#'))
+            ))
+        })
+        .collect::<Vec<_>>();
+    if links.is_empty() {
+        String::new()
+    } else {
+        format!("<p>{}</p>", links.join(" "))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TagQuery {
+    pub page: Option<u64>,
+}
+
+/// Remove posts by projects which no longer agree to be bridged, and by
+/// private projects unless the request comes from a follower, like
+/// [readable_posts](super::outbox::readable_posts) does for cohost's posts
+async fn readable_bridged_posts(
+    state: &State,
+    posts: Vec<BridgedPost>,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> anyhow::Result<Vec<BridgedPost>> {
+    let mut readable_projects = HashMap::new();
+    let mut readable = vec![];
+    for post in posts {
+        let may_read = match readable_projects.get(&post.project_id) {
+            Some(may_read) => *may_read,
+            None => {
+                // Projects which can't be found or no longer consent are hidden
+                let may_read = match fetch_project_by_id(state, post.project_id).await {
+                    Ok(project) => may_read_posts(state, &project, method, uri, headers).await?,
+                    Err(_) => false,
+                };
+                readable_projects.insert(post.project_id, may_read);
+                may_read
+            }
+        };
+        if may_read {
+            readable.push(post);
+        }
+    }
+    Ok(readable)
+}
+
+/// Collection of bridged posts with a hashtag
+pub async fn handle_tag(
+    Path(tag): Path<String>,
+    query: Query<TagQuery>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    state: Extension<Arc<State>>,
+) -> ResponseResult<(HeaderMap, Json<Value>)> {
+    let hashtag = normalize_tag(&tag).ok_or_else(|| ErrorWithStatus {
+        status: StatusCode::NOT_FOUND,
+        message: "not a valid hashtag".to_string(),
+    })?;
+    let collection_id = tag_url(&state.domain, &hashtag);
+    let total_items = state.store.count_posts_with_tag(&hashtag)?;
+
+    let page = match query.page {
+        Some(page) => page,
+        None => return activity_json(&OrderedCollection::paged(collection_id, Some(total_items))),
+    };
+
+    let offset = page.checked_mul(PAGE_SIZE).ok_or_else(|| ErrorWithStatus {
+        status: StatusCode::NOT_FOUND,
+        message: "no such page".to_string(),
+    })?;
+    // Pages past the end are empty, however far past it they are
+    let posts = if offset < total_items {
+        state.store.posts_with_tag(&hashtag, offset, PAGE_SIZE)?
+    } else {
+        vec![]
+    };
+    let items = readable_bridged_posts(&state, posts, &method, &uri, &headers)
+        .await?
+        .iter()
+        .map(|post| post_url(&state.domain, post.project_id, post.post_id))
+        .collect();
+    let pagination = Pagination {
+        current_page: page,
+        more_pages_forward: offset.saturating_add(PAGE_SIZE) < total_items,
+        next_page: page + 1,
+    };
+    activity_json(&OrderedCollectionPage::with_pagination(
+        collection_id,
+        &pagination,
+        items,
+    ))
+}
@@ -1,39 +1,189 @@
 use super::{
-    activitystreams::ActorPage,
+    activitystreams::{actor_url, ActorPage},
     error::{ErrorWithStatus, ResponseResult},
-    server::State,
+    keys::public_key_pem,
+    post::fetch_post,
+    server::{activity_json, State},
 };
-use crate::cohost::types;
+use crate::cohost::types::{self, Project};
 use anyhow::Context;
 use axum::{extract::Path, Extension, Json};
-use http::{header, HeaderMap, StatusCode};
+use http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
 use serde_json::Value;
 use std::sync::Arc;
 
+fn no_such_user() -> ErrorWithStatus {
+    ErrorWithStatus {
+        status: StatusCode::NOT_FOUND,
+        message: "no such user".to_string(),
+    }
+}
+
 pub async fn handle_user(
-    Path(user): Path<String>,
+    Path(project_id): Path<u64>,
     state: Extension<Arc<State>>,
 ) -> ResponseResult<(HeaderMap, Json<Value>)> {
-    let response_value = state.api.query_loader_state(&format!("/{}", &user)).await?;
+    let project = fetch_project_by_id(&state, project_id).await?;
+    activity_json(&actor_page(&state, &project).await?)
+}
+
+/// The actor which `/users/<handle>` was before IDs were based on project
+/// IDs, which followers from then were moved from
+pub async fn handle_legacy_actor(
+    Path(handle): Path<String>,
+    state: Extension<Arc<State>>,
+) -> ResponseResult<(HeaderMap, Json<Value>)> {
+    let project = fetch_aliased_project(&state, &handle).await?;
+    let actor = actor_page(&state, &project).await?;
+    activity_json(&ActorPage::legacy(&actor, &state.domain, &handle))
+}
+
+/// Redirect a URL under `/users/<handle>` to the same path under the
+/// project's canonical actor ID
+pub async fn handle_alias(
+    uri: Uri,
+    state: Extension<Arc<State>>,
+) -> ResponseResult<(StatusCode, HeaderMap)> {
+    let path = uri
+        .path()
+        .strip_prefix("/users/")
+        .ok_or_else(no_such_user)?;
+    let (handle, rest) = path.split_at(path.find('/').unwrap_or(path.len()));
+    let project = fetch_aliased_project(&state, handle).await?;
+
+    let mut location = format!("{}{}", actor_url(&state.domain, project.project_id), rest);
+    if let Some(query) = uri.query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::LOCATION,
+        HeaderValue::from_str(&location).context("invalid redirect location")?,
+    );
+    Ok((StatusCode::FOUND, headers))
+}
+
+/// Look up the project a handle in a URL refers to. Handles a project has
+/// been renamed from keep working until another project takes them
+async fn fetch_aliased_project(state: &State, handle: &str) -> ResponseResult<Project> {
+    match lookup_project(state, handle).await? {
+        Some(project) if state.consent.allows(&project) => {
+            remember_project(state, &project)?;
+            Ok(project)
+        }
+        // Another project has taken the handle
+        Some(_) => Err(no_such_user().into()),
+        None => match state.store.project_with_former_handle(handle)? {
+            Some(project_id) => fetch_project_by_id(state, project_id).await,
+            None => Err(no_such_user().into()),
+        },
+    }
+}
+
+/// The actor bridging a project, with every handle it has had as an alias
+pub async fn actor_page(state: &State, project: &Project) -> anyhow::Result<ActorPage> {
+    let key = state.keys.key_for_project(project.project_id).await?;
+    let mut handles = vec![project.handle.clone()];
+    handles.extend(
+        state
+            .store
+            .former_handles(project.project_id)?
+            .into_iter()
+            .filter(|handle| handle != &project.handle),
+    );
+    Ok(ActorPage::with_project(
+        &state.domain,
+        project,
+        &handles,
+        public_key_pem(&key)?,
+    ))
+}
+
+/// Look up a bridged project by handle. Projects which haven't agreed to be
+/// bridged are treated as if they don't exist
+pub async fn fetch_project(state: &State, handle: &str) -> ResponseResult<Project> {
+    match lookup_project(state, handle).await? {
+        Some(project) if state.consent.allows(&project) => {
+            remember_project(state, &project)?;
+            Ok(project)
+        }
+        _ => Err(no_such_user().into()),
+    }
+}
+
+/// Remember the handle of a project so its actor can be found by ID, since
+/// cohost only finds projects by handle. Renames of followed projects are
+/// left to the poller, which tells their followers about them
+pub fn remember_project(state: &State, project: &Project) -> anyhow::Result<()> {
+    let known = state.store.project_handle(project.project_id)?;
+    if known.as_deref() == Some(project.handle.as_str())
+        || (known.is_some() && !state.store.followers(project.project_id)?.is_empty())
+    {
+        return Ok(());
+    }
+    state
+        .store
+        .set_project_handle(project.project_id, &project.handle)?;
+    Ok(())
+}
+
+/// Look up a bridged project by ID, which only works for projects we've
+/// seen by handle before since cohost only finds projects by handle
+pub async fn fetch_project_by_id(state: &State, project_id: u64) -> ResponseResult<Project> {
+    let handle = state
+        .store
+        .project_handle(project_id)?
+        .ok_or_else(no_such_user)?;
+    let project = match lookup_project(state, &handle).await? {
+        Some(project) if project.project_id == project_id => Some(project),
+        _ => renamed_project(state, project_id, &handle).await?,
+    };
+    match project {
+        Some(project) if state.consent.allows(&project) => Ok(project),
+        _ => Err(no_such_user().into()),
+    }
+}
+
+/// Look up any cohost project by handle, or `None` if cohost says it doesn't
+/// exist. Any other error loading the page is an error
+pub async fn lookup_project(state: &State, handle: &str) -> anyhow::Result<Option<Project>> {
+    let response_value = state
+        .api
+        .query_loader_state(&format!("/{}", handle))
+        .await?;
     match serde_json::from_value::<types::ProjectPageViewLoaderState>(response_value)
         .context("failed to parse cohost response")?
     {
         types::ProjectPageViewLoaderState::ProjectPageView(project_page_view) => {
-            let actor = ActorPage::with_project(&state.domain, &project_page_view.project);
-            let mut headers = HeaderMap::new();
-            headers.insert(
-                header::CONTENT_TYPE,
-                "application/activity+json; charset=utf-8".parse().unwrap(),
-            );
-            Ok((
-                headers,
-                Json(serde_json::to_value(actor).context("unable to serialize actor")?),
-            ))
-        }
-        types::ProjectPageViewLoaderState::Error(_) => Err(ErrorWithStatus {
-            status: StatusCode::NOT_FOUND,
-            message: "no such user".to_string(),
+            Ok(Some(project_page_view.project))
         }
-        .into()),
+        types::ProjectPageViewLoaderState::Error(err) if err.is_not_found() => Ok(None),
+        types::ProjectPageViewLoaderState::Error(err) => Err(err.into()),
     }
 }
+
+/// Find a project which is no longer at `handle`, through its newest bridged
+/// post or else the newest post the poller has seen. Cohost finds posts by ID
+/// whatever handle is in the URL, and tells us who posted them. Returns
+/// `None` if the project wasn't renamed or we have no posts of it to look for
+pub async fn renamed_project(
+    state: &State,
+    project_id: u64,
+    handle: &str,
+) -> anyhow::Result<Option<Project>> {
+    let newest = state
+        .store
+        .bridged_posts(project_id, 0)?
+        .into_iter()
+        .map(|bridged| bridged.post_id)
+        .max();
+    let post_id = match newest.or(state.store.last_seen_post(project_id)?) {
+        Some(post_id) => post_id,
+        None => return Ok(None),
+    };
+    Ok(fetch_post(state, handle, post_id)
+        .await?
+        .map(|post| post.posting_project)
+        .filter(|project| project.project_id == project_id && project.handle != handle))
+}
@@ -1,11 +1,11 @@
 use super::{
-    error::{ErrorWithStatus, ResponseResult},
+    activitystreams::{actor_url, alias_url},
+    error::ResponseResult,
     server::State,
+    user::fetch_project,
 };
-use crate::cohost::types;
-use anyhow::Context;
+use crate::cohost::types::Project;
 use axum::{extract::Query, Extension, Json};
-use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
@@ -39,11 +39,14 @@ pub struct Link {
 }
 
 impl WebFinger {
-    pub fn with_cohost_handle(cohost_handle: &str, local_domain: &str) -> Self {
+    pub fn with_project(project: &Project, local_domain: &str) -> Self {
+        let cohost_handle = &project.handle;
+        let actor = actor_url(local_domain, project.project_id);
         Self {
             subject: format!("acct:{}@{}", cohost_handle, local_domain),
             aliases: vec![
-                format!("https://{}/users/{}", local_domain, cohost_handle),
+                actor.clone(),
+                alias_url(local_domain, cohost_handle),
                 format!("https://cohost.org/{}", cohost_handle),
             ],
             links: vec![
@@ -56,7 +59,7 @@ impl WebFinger {
                 Link {
                     rel: String::from("self"),
                     mime_type: Some(String::from("application/activity+json")),
-                    href: Some(format!("https://{}/users/{}", local_domain, cohost_handle)),
+                    href: Some(actor),
                     template: None,
                 },
             ],
@@ -87,22 +90,8 @@ pub async fn handle_webfinger(
         return Err(anyhow::anyhow!("incorrect domain").into());
     }
 
-    let response_value = state
-        .api
-        .query_loader_state(&format!("/{}", username))
-        .await?;
-    match serde_json::from_value::<types::ProjectPageViewLoaderState>(response_value)
-        .context("failed to parse cohost response")?
-    {
-        types::ProjectPageViewLoaderState::ProjectPageView(_) => {
-            Ok(Json(WebFinger::with_cohost_handle(username, &state.domain)))
-        }
-        types::ProjectPageViewLoaderState::Error(_) => Err(ErrorWithStatus {
-            status: StatusCode::NOT_FOUND,
-            message: "no such user".to_string(),
-        }
-        .into()),
-    }
+    let project = fetch_project(&state, username).await?;
+    Ok(Json(WebFinger::with_project(&project, &state.domain)))
 }
 
 pub async fn handle_host_meta(state: Extension<Arc<State>>) -> String {
@@ -10,32 +10,86 @@ use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 use scraper::Selector;
 use serde::Serialize;
 use serde_json::{json, Value};
+use std::net::IpAddr;
 use std::str::FromStr;
 use tracing::{debug, instrument};
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Where cohost is normally found
+pub const DEFAULT_BASE_URL: &str = "https://cohost.org";
 
 #[derive(Clone, Debug)]
 pub struct CohostApi {
     user_agent: HeaderValue,
     token: Option<String>,
     http_client: Client<HttpsConnector<HttpConnector>>,
+    /// Scheme, authority, and optional path prefix of the cohost instance
+    base_url: Uri,
+}
+
+/// Whether a host is this machine, so plain HTTP can't leave it
+pub(crate) fn is_loopback(host: &str) -> bool {
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map(|addr| addr.is_loopback())
+            .unwrap_or(false)
+}
+
+impl Default for CohostApi {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CohostApi {
     pub fn new() -> Self {
-        let conn = HttpsConnectorBuilder::new()
-            .with_native_roots()
-            .https_only()
-            .enable_http1()
-            .enable_http2()
-            .build();
-
-        Self {
+        Self::with_base_url(Uri::from_static(DEFAULT_BASE_URL)).unwrap()
+    }
+
+    /// Use a cohost instance other than cohost.org, such as a mirror or a
+    /// local stand-in for testing. Plain HTTP is only allowed on loopback
+    pub fn with_base_url(base_url: Uri) -> anyhow::Result<Self> {
+        let host = base_url
+            .host()
+            .ok_or_else(|| anyhow::anyhow!("cohost URL has no host"))?;
+        let builder = HttpsConnectorBuilder::new().with_native_roots();
+        let builder = match base_url.scheme_str() {
+            Some("https") => builder.https_only(),
+            Some("http") if is_loopback(host) => builder.https_or_http(),
+            Some("http") => {
+                return Err(anyhow::anyhow!(
+                    "plain HTTP is only allowed for loopback addresses"
+                ))
+            }
+            _ => return Err(anyhow::anyhow!("cohost URL must be http or https")),
+        };
+        let conn = builder.enable_http1().enable_http2().build();
+
+        Ok(Self {
             user_agent: HeaderValue::from_str(&format!("cobridge/{}", VERSION)).unwrap(),
             token: None,
             http_client: Client::builder().build(conn),
-        }
+            base_url,
+        })
+    }
+
+    /// Build a URI for a path on the cohost instance
+    fn uri(&self, path_and_query: &str) -> anyhow::Result<Uri> {
+        let prefix = self.base_url.path().trim_end_matches('/');
+        Ok(Uri::builder()
+            .scheme(self.base_url.scheme_str().unwrap_or("https"))
+            .authority(
+                self.base_url
+                    .authority()
+                    .ok_or_else(|| anyhow::anyhow!("cohost URL has no host"))?
+                    .as_str(),
+            )
+            .path_and_query(format!("{}{}", prefix, path_and_query))
+            .build()?)
     }
 
     fn request_base(&self, uri: Uri) -> http::request::Builder {
@@ -67,12 +121,7 @@ impl CohostApi {
             urlencoding::encode(&input.to_string())
         );
 
-        let uri = Uri::builder()
-            .scheme("https")
-            .authority("cohost.org")
-            .path_and_query(path)
-            .build()
-            .unwrap();
+        let uri = self.uri(&path)?;
 
         let request = self.request_base(uri).body(Body::empty())?;
 
@@ -119,11 +168,7 @@ impl CohostApi {
     #[instrument(skip(self), err)]
     pub async fn query_loader_state(&self, path_and_query: &str) -> anyhow::Result<Value> {
         debug!("querying loader state");
-        let uri = Uri::builder()
-            .scheme("https")
-            .authority("cohost.org")
-            .path_and_query(path_and_query)
-            .build()?;
+        let uri = self.uri(path_and_query)?;
         let request = self
             .request_base(uri)
             .header(header::ACCEPT, "text/html")
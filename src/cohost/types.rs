@@ -33,12 +33,38 @@ pub struct AttachmentBlock {
     pub preview_url: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+/// The project which sent an [ask](AskBlock). Cohost only sends part of the project
+pub struct AskingProject {
+    pub handle: String,
+    pub display_name: String,
+    pub project_id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+/// A question sent to a project, which the project answers in the rest of the post
+pub struct AskBlock {
+    /// Whether the asker chose not to say who they are
+    pub anon: bool,
+    /// Whether the asker was logged in, even if anonymous
+    pub logged_in: bool,
+    /// Who sent the ask. Missing for anonymous asks
+    pub asking_project: Option<AskingProject>,
+    /// Text of the question, in markdown
+    pub content: String,
+    pub ask_id: String,
+    pub sent_at: chrono::DateTime<chrono::Utc>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "camelCase")]
 /// The basic block used for making a post
 pub enum Block {
     Markdown { markdown: MarkdownBlock },
     Attachment { attachment: AttachmentBlock },
+    Ask { ask: AskBlock },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -121,14 +147,27 @@ pub struct Post {
     pub post_edit_url: String,
     pub post_id: u64,
     pub posting_project: Project,
-    pub published_at: String,
+    pub published_at: chrono::DateTime<chrono::Utc>,
     pub related_projects: Value,
-    pub share_tree: Value,
+    /// Posts this post is sharing, starting with the original post.
+    /// Empty if this is not a share
+    pub share_tree: Vec<Post>,
     pub single_post_page_url: String,
     /// Probably means something but I'm not sure
     pub state: u64,
     pub tags: Vec<String>,
-    pub transparent_share_of_post_id: Value,
+    /// If this is a share with no content of its own, the ID of the shared post
+    pub transparent_share_of_post_id: Option<u64>,
+}
+
+impl Post {
+    /// The post this post directly shares, if any
+    pub fn shared_post(&self) -> Option<&Post> {
+        match self.transparent_share_of_post_id {
+            Some(post_id) => self.share_tree.iter().find(|post| post.post_id == post_id),
+            None => self.share_tree.last(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -170,22 +209,66 @@ pub struct CohostLoaderError {
     error_code: String,
 }
 
+impl CohostLoaderError {
+    /// Whether cohost says the page doesn't exist, rather than failing to load it
+    pub fn is_not_found(&self) -> bool {
+        self.error_code == "not-found"
+    }
+}
+
+impl std::error::Error for CohostLoaderError {}
+
+impl Display for CohostLoaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cohost loader error {}, message \"{}\"",
+            self.error_code, self.message
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub enum ProjectPageViewLoaderState {
-    ProjectPageView(ProjectPageView),
+    ProjectPageView(Box<ProjectPageView>),
     Error(CohostLoaderError),
 }
 
 impl From<ProjectPageViewLoaderState> for Result<ProjectPageView, CohostLoaderError> {
     fn from(state: ProjectPageViewLoaderState) -> Self {
         match state {
-            ProjectPageViewLoaderState::ProjectPageView(view) => Self::Ok(view),
+            ProjectPageViewLoaderState::ProjectPageView(view) => Self::Ok(*view),
             ProjectPageViewLoaderState::Error(err) => Self::Err(err),
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+/// Loader state of a single post's page, at `/<handle>/post/<post_id>`
+pub struct SinglePostView {
+    pub post_id: u64,
+    pub project: Project,
+    pub post: Post,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum SinglePostViewLoaderState {
+    SinglePostView(Box<SinglePostView>),
+    Error(CohostLoaderError),
+}
+
+impl From<SinglePostViewLoaderState> for Result<SinglePostView, CohostLoaderError> {
+    fn from(state: SinglePostViewLoaderState) -> Self {
+        match state {
+            SinglePostViewLoaderState::SinglePostView(view) => Self::Ok(*view),
+            SinglePostViewLoaderState::Error(err) => Self::Err(err),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ErrorData {
@@ -0,0 +1,34 @@
+//! Which cohost projects have agreed to be bridged
+
+use crate::cohost::types::Project;
+use std::collections::HashSet;
+
+/// Projects are only bridged if they ask for it, either by putting a marker
+/// in their profile or by asking the admin to add them to the allowlist
+#[derive(Debug, Clone)]
+pub struct ConsentPolicy {
+    /// Text which opts a project in when found in its headline or
+    /// description, compared case insensitively
+    marker: String,
+    /// IDs of projects bridged regardless of their profile. Handles can
+    /// change hands, so they aren't used
+    allowlist: HashSet<u64>,
+}
+
+impl ConsentPolicy {
+    pub fn new(marker: &str, allowlist: &[u64]) -> Self {
+        Self {
+            marker: marker.to_lowercase(),
+            allowlist: allowlist.iter().copied().collect(),
+        }
+    }
+
+    pub fn allows(&self, project: &Project) -> bool {
+        if self.allowlist.contains(&project.project_id) {
+            return true;
+        }
+        !self.marker.is_empty()
+            && (project.headline.to_lowercase().contains(&self.marker)
+                || project.description.to_lowercase().contains(&self.marker))
+    }
+}
@@ -0,0 +1,57 @@
+#![allow(dead_code)]
+use crate::activitypub::featured::handle_featured;
+use crate::activitypub::followers::handle_followers;
+use crate::activitypub::inbox::{handle_inbox, handle_shared_inbox};
+use crate::activitypub::negotiate::redirect_browsers;
+use crate::activitypub::nodeinfo::{
+    handle_nodeinfo_2_0, handle_nodeinfo_2_1, handle_nodeinfo_links,
+};
+use crate::activitypub::outbox::handle_outbox;
+use crate::activitypub::post::handle_post;
+use crate::activitypub::server::State;
+use crate::activitypub::tags::handle_tag;
+use crate::activitypub::user::{handle_alias, handle_legacy_actor, handle_user};
+use crate::activitypub::webfinger::{handle_host_meta, handle_webfinger};
+use axum::routing::{get, post};
+use axum::{middleware, Extension, Router};
+use std::sync::Arc;
+use tower_http::trace::TraceLayer;
+
+pub mod activitypub;
+pub mod cohost;
+pub mod consent;
+pub mod poller;
+pub mod store;
+
+/// All routes served by the bridge
+pub fn app(state: Arc<State>) -> Router {
+    // Routes which people may open in a browser as well as fetch as ActivityStreams
+    let negotiated = Router::new()
+        .route("/projects/:project_id", get(handle_user))
+        .route("/projects/:project_id/posts/:post_id", get(handle_post))
+        .route("/users/:user", get(handle_legacy_actor))
+        .route("/users/:user/posts/:post_id", get(handle_alias))
+        .route_layer(middleware::from_fn(redirect_browsers));
+
+    Router::new()
+        .route("/.well-known/webfinger", get(handle_webfinger))
+        .route("/.well-known/host-meta", get(handle_host_meta))
+        .route("/.well-known/nodeinfo", get(handle_nodeinfo_links))
+        .route("/nodeinfo/2.0", get(handle_nodeinfo_2_0))
+        .route("/nodeinfo/2.1", get(handle_nodeinfo_2_1))
+        .route("/projects/:project_id/outbox", get(handle_outbox))
+        .route("/projects/:project_id/inbox", post(handle_inbox))
+        .route("/projects/:project_id/followers", get(handle_followers))
+        .route("/projects/:project_id/featured", get(handle_featured))
+        // Handle-based URLs are aliases, except that servers which knew
+        // actors by them may still deliver to the old inboxes
+        .route("/users/:user/outbox", get(handle_alias))
+        .route("/users/:user/inbox", post(handle_inbox))
+        .route("/users/:user/followers", get(handle_alias))
+        .route("/users/:user/featured", get(handle_alias))
+        .route("/inbox", post(handle_shared_inbox))
+        .route("/tags/:tag", get(handle_tag))
+        .merge(negotiated)
+        .layer(TraceLayer::new_for_http())
+        .layer(Extension(state))
+}
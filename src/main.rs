@@ -1,20 +1,19 @@
-#![allow(dead_code)]
-use crate::activitypub::server::State;
-use crate::activitypub::user::handle_user;
-use crate::activitypub::webfinger::{handle_host_meta, handle_webfinger};
-use axum::routing::get;
-use axum::{Extension, Router};
-use cohost::CohostApi;
+use cobridge::activitypub::client::FederationClient;
+use cobridge::activitypub::delivery::{DeliveryConfig, DeliveryQueue};
+use cobridge::activitypub::keys::KeyStore;
+use cobridge::activitypub::server::State;
+use cobridge::cohost::CohostApi;
+use cobridge::consent::ConsentPolicy;
+use cobridge::poller::PollerConfig;
+use cobridge::{activitypub, poller, store};
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
-use tower_http::trace::TraceLayer;
 use tracing::info;
 
-mod activitypub;
-mod cohost;
-
 #[derive(Debug, StructOpt)]
 #[structopt(name = "cobridge", about = "Bridge from cohost to ActivityPub")]
 struct Options {
@@ -22,6 +21,10 @@ struct Options {
     #[structopt(short, long, default_value = "localhost")]
     domain: String,
 
+    /// Base URL of cohost. Plain HTTP is allowed for loopback addresses
+    #[structopt(long, default_value = "https://cohost.org")]
+    cohost_url: http::Uri,
+
     /// Local bind address
     #[structopt(short = "b", long = "bind", default_value = "::")]
     bind_addr: String,
@@ -29,6 +32,58 @@ struct Options {
     /// Port
     #[structopt(short, long, default_value = "8080")]
     port: u16,
+
+    /// Directory to store the database in
+    #[structopt(long, default_value = "data", parse(from_os_str))]
+    data_dir: PathBuf,
+
+    /// Maximum difference in seconds between our clock and the date on signed requests
+    #[structopt(long, default_value = "3600")]
+    clock_skew: i64,
+
+    /// Seconds between checking followed projects for new posts
+    #[structopt(long, default_value = "300")]
+    poll_interval: u64,
+
+    /// Maximum random seconds added to each poll interval
+    #[structopt(long, default_value = "30")]
+    poll_jitter: u64,
+
+    /// Maximum seconds to wait before polling a project which keeps failing
+    #[structopt(long, default_value = "3600")]
+    poll_max_backoff: u64,
+
+    /// Seconds between checks that followed projects still want to be bridged
+    #[structopt(long, default_value = "3600")]
+    consent_interval: u64,
+
+    /// Projects are bridged if their headline or description contains this
+    /// text. Set to an empty string to only bridge allowed projects
+    #[structopt(long, default_value = "#cobridge")]
+    opt_in_marker: String,
+
+    /// ID of a project to bridge even without the opt-in marker.
+    /// May be given more than once
+    #[structopt(long = "allow", number_of_values = 1)]
+    allowed_projects: Vec<u64>,
+
+    /// Seconds to wait for a remote server to accept a delivery
+    #[structopt(long, default_value = "30")]
+    delivery_timeout: u64,
+
+    /// Hours to keep retrying a delivery before giving up
+    #[structopt(long, default_value = "48")]
+    delivery_max_age: u64,
+
+    /// Content warning for 18+ posts which don't have one. Set to an empty
+    /// string to only mark them as sensitive
+    #[structopt(long, default_value = "18+")]
+    adult_content_warning: String,
+
+    /// ID of another server's instance actor, which may see private posts
+    /// if someone on its server follows the project. May be given more than once
+    #[structopt(long = "instance-actor", number_of_values = 1)]
+    instance_actors: Vec<String>,
 }
 
 #[tokio::main]
@@ -42,17 +97,42 @@ async fn main() -> anyhow::Result<()> {
         socket_addr, &options.domain
     );
 
+    std::fs::create_dir_all(&options.data_dir)?;
+    let store: Arc<dyn store::Store> = Arc::new(store::SqliteStore::open(
+        &options.data_dir.join("cobridge.sqlite3"),
+    )?);
+
     let state = Arc::new(State {
-        api: CohostApi::new(),
+        store: store.clone(),
+        api: CohostApi::with_base_url(options.cohost_url.clone())?,
         domain: options.domain.clone(),
+        keys: KeyStore::new(store.clone()),
+        client: FederationClient::new(&options.domain),
+        delivery: DeliveryQueue::new(store),
+        consent: ConsentPolicy::new(&options.opt_in_marker, &options.allowed_projects),
+        adult_content_warning: options.adult_content_warning.clone(),
+        clock_skew: chrono::Duration::seconds(options.clock_skew),
+        instance_actors: options.instance_actors.clone(),
     });
 
-    let app = Router::new()
-        .route("/.well-known/webfinger", get(handle_webfinger))
-        .route("/.well-known/host-meta", get(handle_host_meta))
-        .route("/users/:user", get(handle_user))
-        .layer(TraceLayer::new_for_http())
-        .layer(Extension(state));
+    tokio::spawn(activitypub::delivery::run(
+        state.clone(),
+        DeliveryConfig {
+            timeout: Duration::from_secs(options.delivery_timeout),
+            max_age: Duration::from_secs(options.delivery_max_age * 60 * 60),
+        },
+    ));
+    tokio::spawn(poller::run(
+        state.clone(),
+        PollerConfig {
+            interval: Duration::from_secs(options.poll_interval),
+            jitter: Duration::from_secs(options.poll_jitter),
+            max_backoff: Duration::from_secs(options.poll_max_backoff),
+            consent_interval: Duration::from_secs(options.consent_interval),
+        },
+    ));
+
+    let app = cobridge::app(state);
 
     axum::Server::bind(&socket_addr)
         .serve(app.into_make_service())
@@ -0,0 +1,498 @@
+//! Background task which polls cohost for new posts by followed projects
+//! and sends them to followers
+
+use crate::activitypub::tags::normalize_tag;
+use crate::activitypub::{
+    activitystreams::{actor_url, alias_url, post_url, Activity, PostActivity, Tombstone},
+    followers::Follower,
+    post::{bridged_share, fetch_post},
+    server::State,
+    user::{actor_page, lookup_project, remember_project, renamed_project},
+};
+use crate::cohost::types::{Post, Privacy, ProfilePostsInput, ProfilePostsInputOptions, Project};
+use crate::store::BridgedPost;
+use anyhow::Context;
+use rand::Rng;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+/// How many pages of posts to look through for new posts in one poll
+const MAX_PAGES: u64 = 5;
+
+#[derive(Debug, Clone)]
+pub struct PollerConfig {
+    /// Time between polls
+    pub interval: Duration,
+    /// Maximum random time added to each interval, so we don't poll at
+    /// exactly the same time every time
+    pub jitter: Duration,
+    /// Maximum time to wait before polling a project which keeps failing
+    pub max_backoff: Duration,
+    /// Time between checks that a project still wants to be bridged, and
+    /// that its older posts still exist
+    pub consent_interval: Duration,
+}
+
+/// Consecutive failures for a project and when it may next be polled
+struct Backoff {
+    failures: u32,
+    next_poll: Instant,
+}
+
+/// Poll every followed project forever
+pub async fn run(state: Arc<State>, config: PollerConfig) {
+    let mut backoff: HashMap<u64, Backoff> = HashMap::new();
+    let mut consent_checked: HashMap<u64, Instant> = HashMap::new();
+    loop {
+        let jitter = rand::thread_rng().gen_range(Duration::ZERO..=config.jitter);
+        tokio::time::sleep(config.interval + jitter).await;
+
+        let projects = match state.store.followed_projects() {
+            Ok(projects) => projects,
+            Err(err) => {
+                warn!("failed to list followed projects: {:#}", err);
+                continue;
+            }
+        };
+
+        for (project_id, handle) in projects {
+            if let Some(project_backoff) = backoff.get(&project_id) {
+                if project_backoff.next_poll > Instant::now() {
+                    debug!("skipping {} due to backoff", &handle);
+                    continue;
+                }
+            }
+
+            let consent_due = consent_checked
+                .get(&project_id)
+                .is_none_or(|checked| checked.elapsed() >= config.consent_interval);
+            let mut check_old = false;
+            if consent_due {
+                match check_consent(&state, project_id, &handle).await {
+                    Ok(true) => {
+                        consent_checked.insert(project_id, Instant::now());
+                        check_old = true;
+                    }
+                    Ok(false) => {
+                        consent_checked.remove(&project_id);
+                        backoff.remove(&project_id);
+                        continue;
+                    }
+                    // Keep bridging, since they agreed last time we could check
+                    Err(err) => warn!("failed to check consent of {}: {:#}", &handle, err),
+                }
+            }
+
+            match poll_project(&state, project_id, &handle).await {
+                Ok(()) => {
+                    backoff.remove(&project_id);
+                    if check_old {
+                        if let Err(err) = check_old_posts(&state, project_id).await {
+                            warn!("failed to check old posts of {}: {:#}", &handle, err);
+                        }
+                    }
+                }
+                Err(err) => {
+                    let failures = backoff.get(&project_id).map_or(0, |b| b.failures) + 1;
+                    let delay = config
+                        .interval
+                        .saturating_mul(2u32.saturating_pow(failures))
+                        .min(config.max_backoff);
+                    warn!(
+                        "failed to poll {} ({} failures), waiting {:?}: {:#}",
+                        &handle, failures, delay, err
+                    );
+                    backoff.insert(
+                        project_id,
+                        Backoff {
+                            failures,
+                            next_poll: Instant::now() + delay,
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Hash of everything in a post which can be edited and is bridged
+pub fn content_hash(post: &Post) -> String {
+    let content = json!([&post.headline, &post.blocks, &post.tags, &post.cws]);
+    format!("{:x}", Sha256::digest(content.to_string()))
+}
+
+/// Look for posts newer than the last one we saw and send them to followers,
+/// and send updates or deletes for any edited or deleted posts among the
+/// ones we looked at. If the project has been renamed, followers are told
+/// and it is polled under its new handle
+pub async fn poll_project(state: &State, project_id: u64, handle: &str) -> anyhow::Result<()> {
+    debug!("polling {}", handle);
+    let last_seen = state.store.last_seen_post(project_id)?;
+
+    let mut new_posts: Vec<Post> = Vec::new();
+    let mut seen_posts: Vec<Post> = Vec::new();
+    let mut newest = last_seen;
+    // Every post listed, and the oldest one listed in order. Bridged posts
+    // in that range which weren't listed may have been deleted
+    let mut listed: HashSet<u64> = HashSet::new();
+    let mut oldest_listed: Option<u64> = None;
+    let mut reached_end = false;
+    for page in 0..MAX_PAGES {
+        let data = match state
+            .api
+            .trpc_query_single(&ProfilePostsInput {
+                project_handle: handle.to_string(),
+                page,
+                options: ProfilePostsInputOptions {
+                    hide_replies: false,
+                    hide_shares: false,
+                },
+            })
+            .await?
+        {
+            Ok(data) => data,
+            Err(err) if page == 0 && err.data.http_status == 404 => {
+                return poll_renamed_project(state, project_id, handle, err.into()).await;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        // After a rename another project may have taken the handle
+        if page == 0
+            && data
+                .posts
+                .iter()
+                .any(|post| post.posting_project.project_id != project_id)
+        {
+            let err = anyhow::anyhow!("{} belongs to another project now", handle);
+            return poll_renamed_project(state, project_id, handle, err).await;
+        }
+
+        let mut reached_seen = false;
+        reached_end = !data.pagination.more_pages_forward;
+        for post in data
+            .posts
+            .into_iter()
+            .filter(|post| post.posting_project.project_id == project_id)
+        {
+            newest = newest.max(Some(post.post_id));
+            listed.insert(post.post_id);
+            if !post.pinned {
+                oldest_listed = Some(oldest_listed.map_or(post.post_id, |id| id.min(post.post_id)));
+            }
+            match last_seen {
+                Some(last_seen) if post.post_id <= last_seen => {
+                    // Pinned posts are shown first even when they are old
+                    if !post.pinned {
+                        reached_seen = true;
+                    }
+                    seen_posts.push(post);
+                }
+                _ => new_posts.push(post),
+            }
+        }
+
+        // The first time we see a project we only want to know where to start
+        if last_seen.is_none() || reached_seen || !data.pagination.more_pages_forward {
+            break;
+        }
+    }
+
+    if let Some(last_seen) = last_seen {
+        new_posts.sort_by_key(|post| post.post_id);
+        new_posts.dedup_by_key(|post| post.post_id);
+        let actor = actor_url(&state.domain, project_id);
+        for post in &new_posts {
+            info!("new post {} by {}", post.post_id, handle);
+            bridge_shared_post(state, post)?;
+            let activity = PostActivity::with_post(
+                &state.domain,
+                post,
+                &state.adult_content_warning,
+                bridged_share(state, post)?,
+            );
+            state
+                .delivery
+                .deliver_to_followers(project_id, &actor, &activity)?;
+            record_post(state, project_id, handle, post)?;
+        }
+        debug!("{} new posts since {}", new_posts.len(), last_seen);
+
+        seen_posts.sort_by_key(|post| post.post_id);
+        seen_posts.dedup_by_key(|post| post.post_id);
+        for post in &seen_posts {
+            check_for_edit(state, project_id, handle, post)?;
+        }
+
+        let since = match (reached_end, oldest_listed) {
+            (true, _) => 0,
+            (false, Some(oldest_listed)) => oldest_listed,
+            (false, None) => u64::MAX,
+        };
+        // Posts found missing by [check_old_posts] are checked again too
+        for bridged in state.store.bridged_posts(project_id, 0)? {
+            if bridged.post_id < since && bridged.missing.is_none() {
+                continue;
+            }
+            if !listed.contains(&bridged.post_id) {
+                check_deleted(state, handle, &bridged).await?;
+            } else if bridged.missing.is_some() {
+                state.store.set_post_missing(bridged.post_id, None)?;
+            }
+        }
+    }
+
+    if let Some(newest) = newest {
+        if Some(newest) != last_seen {
+            state.store.set_last_seen_post(project_id, newest)?;
+        }
+    }
+    move_legacy_followers(state, project_id)
+}
+
+/// Poll a project which is no longer at `handle` under its new handle,
+/// after telling followers about the rename. `err` is returned if the
+/// project can't be found
+async fn poll_renamed_project(
+    state: &State,
+    project_id: u64,
+    handle: &str,
+    err: anyhow::Error,
+) -> anyhow::Result<()> {
+    match renamed_project(state, project_id, handle).await? {
+        Some(project) => {
+            record_handle(state, &project).await?;
+            Box::pin(poll_project(state, project_id, &project.handle)).await
+        }
+        None => Err(err),
+    }
+}
+
+fn record_post(state: &State, project_id: u64, handle: &str, post: &Post) -> anyhow::Result<()> {
+    state.store.record_post(
+        &BridgedPost {
+            post_id: post.post_id,
+            project_id,
+            handle: handle.to_string(),
+            published: post.published_at,
+            content_hash: content_hash(post),
+            deleted: None,
+            url: post.single_post_page_url.clone(),
+            missing: None,
+            private: post.posting_project.privacy == Privacy::Private,
+        },
+        &post
+            .tags
+            .iter()
+            .filter_map(|tag| normalize_tag(tag))
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Bridge the post a new post shares, if its project agreed to be bridged,
+/// so the share can announce or quote it. Its project's handle is kept so
+/// the shared post's ID can be fetched
+fn bridge_shared_post(state: &State, post: &Post) -> anyhow::Result<()> {
+    let shared = match post.shared_post() {
+        Some(shared) => shared,
+        None => return Ok(()),
+    };
+    let project = &shared.posting_project;
+    if !state.consent.allows(project) || state.store.bridged_post(shared.post_id)?.is_some() {
+        return Ok(());
+    }
+    remember_project(state, project)?;
+    record_post(state, project.project_id, &project.handle, shared)
+}
+
+/// Send an `Update` to followers if a post we already bridged has changed
+fn check_for_edit(state: &State, project_id: u64, handle: &str, post: &Post) -> anyhow::Result<()> {
+    let bridged = match state.store.bridged_post(post.post_id)? {
+        Some(bridged) => bridged,
+        None => return Ok(()),
+    };
+    if bridged.content_hash == content_hash(post) {
+        return Ok(());
+    }
+
+    // Posts bridged before we kept hashes have nothing to compare against,
+    // and transparent shares have no content of their own to update
+    if !bridged.content_hash.is_empty() {
+        if let PostActivity::Create(create) = PostActivity::with_post(
+            &state.domain,
+            post,
+            &state.adult_content_warning,
+            bridged_share(state, post)?,
+        ) {
+            info!("post {} by {} was edited", post.post_id, handle);
+            let update = Activity::update(create.object, chrono::Utc::now());
+            state.delivery.deliver_to_followers(
+                project_id,
+                &actor_url(&state.domain, project_id),
+                &update,
+            )?;
+        }
+    }
+    record_post(state, project_id, handle, post)
+}
+
+/// Check whether a bridged post is still on cohost. The first time cohost
+/// says it doesn't exist only that is remembered, and if it's still missing
+/// on a later poll followers are sent a `Delete`. Errors loading the post
+/// don't count, since it may well still be there
+async fn check_deleted(state: &State, handle: &str, bridged: &BridgedPost) -> anyhow::Result<()> {
+    match fetch_post(state, handle, bridged.post_id).await {
+        Ok(Some(_)) => {
+            if bridged.missing.is_some() {
+                state.store.set_post_missing(bridged.post_id, None)?;
+            }
+            return Ok(());
+        }
+        Ok(None) => {}
+        Err(err) => {
+            warn!(
+                "failed to check whether post {} by {} exists: {:#}",
+                bridged.post_id, handle, err
+            );
+            return Ok(());
+        }
+    }
+    if bridged.missing.is_none() {
+        debug!("post {} by {} is missing", bridged.post_id, handle);
+        return state
+            .store
+            .set_post_missing(bridged.post_id, Some(chrono::Utc::now()));
+    }
+
+    info!("post {} by {} was deleted", bridged.post_id, handle);
+    let deleted = chrono::Utc::now();
+    let actor = actor_url(&state.domain, bridged.project_id);
+    let delete = Activity::delete(
+        actor.clone(),
+        Tombstone::note(
+            post_url(&state.domain, bridged.project_id, bridged.post_id),
+            Some(deleted),
+        ),
+        bridged.private,
+    );
+    state
+        .delivery
+        .deliver_to_followers(bridged.project_id, &actor, &delete)?;
+    state.store.delete_post(bridged.post_id, deleted)
+}
+
+/// Check every bridged post of a project which isn't already missing, since
+/// polls only look at the newest ones. Posts which are missing are checked
+/// again on the next poll, and deleted if they are still missing
+pub async fn check_old_posts(state: &State, project_id: u64) -> anyhow::Result<()> {
+    // The poll before this may have found a rename
+    let handle = state
+        .store
+        .project_handle(project_id)?
+        .with_context(|| format!("project {} has no handle", project_id))?;
+    for bridged in state.store.bridged_posts(project_id, 0)? {
+        if bridged.missing.is_none() {
+            check_deleted(state, &handle, &bridged).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Check that a followed project still wants to be bridged. If it has opted
+/// out, followers are sent a `Delete` of the actor and removed, which stops
+/// it being polled, and its posts are deleted. If the project can't be found
+/// that's an error, so whatever was decided last time stands
+pub async fn check_consent(state: &State, project_id: u64, handle: &str) -> anyhow::Result<bool> {
+    let project = match lookup_project(state, handle).await? {
+        Some(project) if project.project_id == project_id => Some(project),
+        // The handle may belong to another project now
+        _ => renamed_project(state, project_id, handle).await?,
+    };
+    let project = project.with_context(|| {
+        format!(
+            "project {} is no longer at {} and can't be found through its posts",
+            project_id, handle
+        )
+    })?;
+    record_handle(state, &project).await?;
+    if state.consent.allows(&project) {
+        return Ok(true);
+    }
+
+    info!("{} no longer wants to be bridged", handle);
+    let actor = actor_url(&state.domain, project_id);
+    state.delivery.deliver_to_followers(
+        project_id,
+        &actor,
+        &Activity::delete_actor(actor.clone()),
+    )?;
+    for follower in state.store.followers(project_id)? {
+        state.store.remove_follower(project_id, &follower.actor)?;
+    }
+    for request in state.store.follow_requests(project_id)? {
+        state
+            .store
+            .remove_follow_request(project_id, &request.follower.actor)?;
+    }
+    // Which also takes them out of tags and statistics
+    let deleted = chrono::Utc::now();
+    for bridged in state.store.bridged_posts(project_id, 0)? {
+        state.store.delete_post(bridged.post_id, deleted)?;
+    }
+    Ok(false)
+}
+
+/// Remember the current handle of a project. If it was renamed, followers
+/// are sent the actor with its new handle, keeping the old one as an alias
+pub async fn record_handle(state: &State, project: &Project) -> anyhow::Result<()> {
+    let previous = match state
+        .store
+        .set_project_handle(project.project_id, &project.handle)?
+    {
+        Some(previous) => previous,
+        None => return Ok(()),
+    };
+
+    info!("{} was renamed to {}", previous, &project.handle);
+    if state.store.followers(project.project_id)?.is_empty() {
+        return Ok(());
+    }
+    let update = Activity::update_actor(actor_page(state, project).await?);
+    state
+        .delivery
+        .deliver_to_followers(project.project_id, &update.actor, &update)?;
+    Ok(())
+}
+
+/// Send a `Move` to followers who follow an actor ID based on a handle,
+/// from that ID to the one based on the project ID. Their servers then
+/// follow the new ID and undo their old follow
+fn move_legacy_followers(state: &State, project_id: u64) -> anyhow::Result<()> {
+    let target = actor_url(&state.domain, project_id);
+    for follower in state.store.followers(project_id)? {
+        let legacy_handle = match &follower.legacy_handle {
+            Some(legacy_handle) => legacy_handle,
+            None => continue,
+        };
+        info!("moving {} from {}", &follower.actor, legacy_handle);
+        let origin = alias_url(&state.domain, legacy_handle);
+        state.delivery.deliver(
+            project_id,
+            &origin,
+            &follower.inbox,
+            &Activity::move_actor(origin.clone(), target.clone()),
+        )?;
+        state.store.add_follower(
+            project_id,
+            &Follower {
+                legacy_handle: None,
+                ..follower
+            },
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+//! Import of the files kept in the data directory before the SQLite store.
+//! Runs once, in the same transaction as the first migration, so a fresh
+//! database starts with everything the old version knew about
+
+use crate::activitypub::{delivery::Delivery, followers::Follower};
+use anyhow::Context;
+use rusqlite::{params, Transaction};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Contents of `followers/<project_id>.json`
+#[derive(Deserialize)]
+struct ProjectFollowers {
+    handle: String,
+    followers: Vec<Follower>,
+}
+
+/// Files in `directory` with the given extension, along with their name
+/// without it. A missing directory has no files
+fn files(directory: &Path, extension: &str) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", directory)),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        let stem = if extension.is_empty() {
+            Some(name)
+        } else {
+            name.strip_suffix(extension)
+                .and_then(|stem| stem.strip_suffix('.'))
+        };
+        // Skip temporary files left behind by interrupted writes
+        match stem {
+            Some(stem) if !stem.contains('.') => files.push((stem.to_string(), path.clone())),
+            _ => continue,
+        }
+    }
+    Ok(files)
+}
+
+/// Project ID from a file named after it, or `None` with a warning if it isn't one
+fn project_id(name: &str, path: &Path) -> Option<i64> {
+    match name.parse::<u64>() {
+        Ok(project_id) => Some(project_id as i64),
+        Err(_) => {
+            warn!("skipping unexpected file {:?}", path);
+            None
+        }
+    }
+}
+
+/// Copy the keys, followers, seen posts and pending deliveries stored under
+/// `data_dir` into the tables created by the first migration
+pub fn import(transaction: &Transaction, data_dir: &Path) -> anyhow::Result<()> {
+    let mut imported = 0;
+
+    for (name, path) in files(&data_dir.join("keys"), "pem")? {
+        let project_id = match project_id(&name, &path) {
+            Some(project_id) => project_id,
+            None => continue,
+        };
+        let pem = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {:?}", &path))?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO keys (project_id, private_key_pem) VALUES (?, ?)",
+            params![project_id, pem],
+        )?;
+        imported += 1;
+    }
+
+    for (name, path) in files(&data_dir.join("followers"), "json")? {
+        let project_id = match project_id(&name, &path) {
+            Some(project_id) => project_id,
+            None => continue,
+        };
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {:?}", &path))?;
+        let project: ProjectFollowers = serde_json::from_str(&text)
+            .with_context(|| format!("invalid followers in {:?}", &path))?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO projects (project_id, handle) VALUES (?, ?)",
+            params![project_id, &project.handle],
+        )?;
+        for follower in &project.followers {
+            transaction.execute(
+                "INSERT OR REPLACE INTO followers (project_id, actor, inbox, shared_inbox)
+                 VALUES (?, ?, ?, ?)",
+                params![
+                    project_id,
+                    &follower.actor,
+                    &follower.inbox,
+                    &follower.shared_inbox
+                ],
+            )?;
+        }
+        imported += 1;
+    }
+
+    for (name, path) in files(&data_dir.join("seen"), "")? {
+        let project_id = match project_id(&name, &path) {
+            Some(project_id) => project_id,
+            None => continue,
+        };
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {:?}", &path))?;
+        let post_id: u64 = text
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid seen post in {:?}", &path))?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO seen_posts (project_id, post_id) VALUES (?, ?)",
+            params![project_id, post_id as i64],
+        )?;
+        imported += 1;
+    }
+
+    for (_, path) in files(&data_dir.join("deliveries"), "json")? {
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {:?}", &path))?;
+        let delivery: Delivery = serde_json::from_str(&text)
+            .with_context(|| format!("invalid delivery in {:?}", &path))?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO deliveries
+             (id, project_id, actor, inbox, activity, created, attempts, next_attempt)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                &delivery.id,
+                delivery.project_id as i64,
+                &delivery.actor,
+                &delivery.inbox,
+                &delivery.activity,
+                &delivery.created,
+                delivery.attempts,
+                &delivery.next_attempt,
+            ],
+        )?;
+        imported += 1;
+    }
+
+    if imported > 0 {
+        info!("imported {} files from {:?}", imported, data_dir);
+    }
+    Ok(())
+}
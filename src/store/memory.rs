@@ -0,0 +1,329 @@
+use super::{BridgedPost, CachedActor, Store};
+use crate::activitypub::{
+    delivery::Delivery,
+    followers::{FollowRequest, Follower},
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct Data {
+    keys: HashMap<u64, String>,
+    handles: HashMap<u64, String>,
+    former_handles: HashMap<u64, Vec<String>>,
+    followers: HashMap<u64, Vec<Follower>>,
+    follow_requests: HashMap<u64, Vec<FollowRequest>>,
+    seen_posts: HashMap<u64, u64>,
+    /// Bridged posts and their lowercase hashtags
+    posts: HashMap<u64, (BridgedPost, Vec<String>)>,
+    deliveries: HashMap<String, Delivery>,
+    remote_actors: HashMap<String, CachedActor>,
+}
+
+/// Store which keeps everything in memory, for tests
+#[derive(Default)]
+pub struct MemoryStore {
+    data: Mutex<Data>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Store for MemoryStore {
+    fn key(&self, project_id: u64) -> anyhow::Result<Option<String>> {
+        Ok(self.data.lock().unwrap().keys.get(&project_id).cloned())
+    }
+
+    fn set_key(&self, project_id: u64, private_key_pem: &str) -> anyhow::Result<()> {
+        self.data
+            .lock()
+            .unwrap()
+            .keys
+            .insert(project_id, private_key_pem.to_string());
+        Ok(())
+    }
+
+    fn count_projects(&self) -> anyhow::Result<u64> {
+        Ok(self.data.lock().unwrap().keys.len() as u64)
+    }
+
+    fn project_handle(&self, project_id: u64) -> anyhow::Result<Option<String>> {
+        Ok(self.data.lock().unwrap().handles.get(&project_id).cloned())
+    }
+
+    fn set_project_handle(&self, project_id: u64, handle: &str) -> anyhow::Result<Option<String>> {
+        let mut data = self.data.lock().unwrap();
+        let previous = data.handles.insert(project_id, handle.to_string());
+        match previous {
+            Some(previous) if previous != handle => {
+                let former = data.former_handles.entry(project_id).or_default();
+                if !former.contains(&previous) {
+                    former.push(previous.clone());
+                    former.sort();
+                }
+                Ok(Some(previous))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn former_handles(&self, project_id: u64) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .former_handles
+            .get(&project_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn project_with_former_handle(&self, handle: &str) -> anyhow::Result<Option<u64>> {
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .former_handles
+            .iter()
+            .filter(|(_, former)| former.iter().any(|former| former == handle))
+            .map(|(project_id, _)| *project_id)
+            .min())
+    }
+
+    fn add_follower(&self, project_id: u64, follower: &Follower) -> anyhow::Result<()> {
+        let mut data = self.data.lock().unwrap();
+        let followers = data.followers.entry(project_id).or_default();
+        followers.retain(|existing| existing.actor != follower.actor);
+        followers.push(follower.clone());
+        Ok(())
+    }
+
+    fn remove_follower(&self, project_id: u64, actor: &str) -> anyhow::Result<bool> {
+        let mut data = self.data.lock().unwrap();
+        let followers = data.followers.entry(project_id).or_default();
+        let count = followers.len();
+        followers.retain(|existing| existing.actor != actor);
+        Ok(followers.len() != count)
+    }
+
+    fn followers(&self, project_id: u64) -> anyhow::Result<Vec<Follower>> {
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .followers
+            .get(&project_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn add_follow_request(&self, project_id: u64, request: &FollowRequest) -> anyhow::Result<()> {
+        let mut data = self.data.lock().unwrap();
+        let requests = data.follow_requests.entry(project_id).or_default();
+        requests.retain(|existing| existing.follower.actor != request.follower.actor);
+        requests.push(request.clone());
+        Ok(())
+    }
+
+    fn remove_follow_request(&self, project_id: u64, actor: &str) -> anyhow::Result<bool> {
+        let mut data = self.data.lock().unwrap();
+        let requests = data.follow_requests.entry(project_id).or_default();
+        let count = requests.len();
+        requests.retain(|existing| existing.follower.actor != actor);
+        Ok(requests.len() != count)
+    }
+
+    fn follow_requests(&self, project_id: u64) -> anyhow::Result<Vec<FollowRequest>> {
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .follow_requests
+            .get(&project_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn followed_projects(&self) -> anyhow::Result<Vec<(u64, String)>> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .followers
+            .iter()
+            .filter(|(_, followers)| !followers.is_empty())
+            .filter_map(|(project_id, _)| {
+                Some((*project_id, data.handles.get(project_id)?.clone()))
+            })
+            .collect())
+    }
+
+    fn last_seen_post(&self, project_id: u64) -> anyhow::Result<Option<u64>> {
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .seen_posts
+            .get(&project_id)
+            .copied())
+    }
+
+    fn set_last_seen_post(&self, project_id: u64, post_id: u64) -> anyhow::Result<()> {
+        self.data
+            .lock()
+            .unwrap()
+            .seen_posts
+            .insert(project_id, post_id);
+        Ok(())
+    }
+
+    fn record_post(&self, post: &BridgedPost, hashtags: &[String]) -> anyhow::Result<()> {
+        let hashtags = hashtags.iter().map(|tag| tag.to_lowercase()).collect();
+        self.data
+            .lock()
+            .unwrap()
+            .posts
+            .insert(post.post_id, (post.clone(), hashtags));
+        Ok(())
+    }
+
+    fn bridged_post(&self, post_id: u64) -> anyhow::Result<Option<BridgedPost>> {
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .posts
+            .get(&post_id)
+            .map(|(post, _)| post.clone()))
+    }
+
+    fn bridged_posts(
+        &self,
+        project_id: u64,
+        since_post_id: u64,
+    ) -> anyhow::Result<Vec<BridgedPost>> {
+        let mut posts = self
+            .data
+            .lock()
+            .unwrap()
+            .posts
+            .values()
+            .map(|(post, _)| post)
+            .filter(|post| {
+                post.project_id == project_id
+                    && post.post_id >= since_post_id
+                    && post.deleted.is_none()
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        posts.sort_by_key(|post| post.post_id);
+        Ok(posts)
+    }
+
+    fn delete_post(
+        &self,
+        post_id: u64,
+        deleted: chrono::DateTime<chrono::Utc>,
+    ) -> anyhow::Result<()> {
+        if let Some((post, hashtags)) = self.data.lock().unwrap().posts.get_mut(&post_id) {
+            post.deleted = Some(deleted);
+            hashtags.clear();
+        }
+        Ok(())
+    }
+
+    fn set_post_missing(
+        &self,
+        post_id: u64,
+        missing: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> anyhow::Result<()> {
+        if let Some((post, _)) = self.data.lock().unwrap().posts.get_mut(&post_id) {
+            post.missing = missing;
+        }
+        Ok(())
+    }
+
+    fn posts_with_tag(
+        &self,
+        hashtag: &str,
+        offset: u64,
+        limit: u64,
+    ) -> anyhow::Result<Vec<BridgedPost>> {
+        let hashtag = hashtag.to_lowercase();
+        let data = self.data.lock().unwrap();
+        let mut posts = data
+            .posts
+            .values()
+            .filter(|(_, hashtags)| hashtags.contains(&hashtag))
+            .map(|(post, _)| post.clone())
+            .collect::<Vec<_>>();
+        posts.sort_by_key(|post| std::cmp::Reverse(post.published));
+        Ok(posts
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect())
+    }
+
+    fn count_posts_with_tag(&self, hashtag: &str) -> anyhow::Result<u64> {
+        let hashtag = hashtag.to_lowercase();
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .posts
+            .values()
+            .filter(|(_, hashtags)| hashtags.contains(&hashtag))
+            .count() as u64)
+    }
+
+    fn count_posts(&self) -> anyhow::Result<u64> {
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .posts
+            .values()
+            .filter(|(post, _)| post.deleted.is_none())
+            .count() as u64)
+    }
+
+    fn save_delivery(&self, delivery: &Delivery) -> anyhow::Result<()> {
+        self.data
+            .lock()
+            .unwrap()
+            .deliveries
+            .insert(delivery.id.clone(), delivery.clone());
+        Ok(())
+    }
+
+    fn remove_delivery(&self, id: &str) -> anyhow::Result<()> {
+        self.data.lock().unwrap().deliveries.remove(id);
+        Ok(())
+    }
+
+    fn deliveries(&self) -> anyhow::Result<Vec<Delivery>> {
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .deliveries
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    fn cached_actor(&self, id: &str) -> anyhow::Result<Option<CachedActor>> {
+        Ok(self.data.lock().unwrap().remote_actors.get(id).cloned())
+    }
+
+    fn cache_actor(&self, id: &str, actor: &CachedActor) -> anyhow::Result<()> {
+        self.data
+            .lock()
+            .unwrap()
+            .remote_actors
+            .insert(id.to_string(), actor.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+-- Signing key for each bridged project
+CREATE TABLE keys (
+    project_id INTEGER PRIMARY KEY,
+    private_key_pem TEXT NOT NULL
+);
+
+-- Handle of each project which has been followed, so it can be polled
+CREATE TABLE projects (
+    project_id INTEGER PRIMARY KEY,
+    handle TEXT NOT NULL
+);
+
+CREATE TABLE followers (
+    project_id INTEGER NOT NULL,
+    actor TEXT NOT NULL,
+    inbox TEXT NOT NULL,
+    shared_inbox TEXT,
+    PRIMARY KEY (project_id, actor)
+);
+
+-- Newest post seen by the poller for each project
+CREATE TABLE seen_posts (
+    project_id INTEGER PRIMARY KEY,
+    post_id INTEGER NOT NULL
+);
+
+CREATE TABLE deliveries (
+    id TEXT PRIMARY KEY,
+    project_id INTEGER NOT NULL,
+    actor TEXT NOT NULL,
+    inbox TEXT NOT NULL,
+    activity TEXT NOT NULL,
+    created TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    next_attempt TEXT NOT NULL
+);
+
+-- Documents of remote actors, used to avoid fetching keys for every request
+CREATE TABLE remote_actors (
+    id TEXT PRIMARY KEY,
+    document TEXT NOT NULL,
+    fetched TEXT NOT NULL
+);
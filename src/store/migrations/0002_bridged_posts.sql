@@ -0,0 +1,16 @@
+-- Posts which have been sent to followers
+CREATE TABLE bridged_posts (
+    post_id INTEGER PRIMARY KEY,
+    project_id INTEGER NOT NULL,
+    handle TEXT NOT NULL,
+    published TEXT NOT NULL
+);
+
+-- Normalized hashtags of bridged posts, stored in lowercase
+CREATE TABLE post_tags (
+    post_id INTEGER NOT NULL REFERENCES bridged_posts (post_id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (post_id, tag)
+);
+
+CREATE INDEX post_tags_tag ON post_tags (tag);
@@ -0,0 +1,3 @@
+-- Hash of each bridged post's content, to notice edits. Posts bridged before
+-- this was kept have an empty hash
+ALTER TABLE bridged_posts ADD COLUMN content_hash TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,2 @@
+-- When a bridged post was found to be deleted on cohost
+ALTER TABLE bridged_posts ADD COLUMN deleted TEXT;
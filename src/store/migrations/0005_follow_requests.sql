@@ -0,0 +1,10 @@
+-- Follows of private projects, which aren't accepted automatically
+CREATE TABLE follow_requests (
+    project_id INTEGER NOT NULL,
+    actor TEXT NOT NULL,
+    inbox TEXT NOT NULL,
+    shared_inbox TEXT,
+    activity TEXT NOT NULL,
+    received TEXT NOT NULL,
+    PRIMARY KEY (project_id, actor)
+);
@@ -0,0 +1,12 @@
+-- Handles projects had before being renamed, which stay aliases of their actors
+CREATE TABLE former_handles (
+    project_id INTEGER NOT NULL,
+    handle TEXT NOT NULL,
+    PRIMARY KEY (project_id, handle)
+);
+
+-- Actor IDs used to be based on handles. Followers from then are sent a
+-- `Move` to the actor's ID based on the project ID
+ALTER TABLE followers ADD COLUMN legacy_handle TEXT;
+UPDATE followers SET legacy_handle =
+    (SELECT handle FROM projects WHERE projects.project_id = followers.project_id);
@@ -0,0 +1,3 @@
+-- Page of each bridged post on cohost, to send browsers to. Empty for posts
+-- bridged before it was kept
+ALTER TABLE bridged_posts ADD COLUMN url TEXT NOT NULL DEFAULT '';
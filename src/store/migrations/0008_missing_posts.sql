@@ -0,0 +1,3 @@
+-- When cohost first said a bridged post doesn't exist. It is only deleted if
+-- it is still missing on a later poll
+ALTER TABLE bridged_posts ADD COLUMN missing TEXT;
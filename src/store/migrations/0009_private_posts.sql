@@ -0,0 +1,4 @@
+-- Whether a bridged post was only sent to followers, so its Delete is too.
+-- Posts bridged before this was kept are treated as private, so deleting
+-- them never tells anyone new about them
+ALTER TABLE bridged_posts ADD COLUMN private INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,122 @@
+//! Persistent state of the bridge
+
+use crate::activitypub::{
+    delivery::Delivery,
+    followers::{FollowRequest, Follower},
+};
+
+mod legacy;
+pub mod memory;
+pub mod sqlite;
+
+pub use self::memory::MemoryStore;
+pub use self::sqlite::SqliteStore;
+
+/// A remote actor document, as last fetched
+#[derive(Debug, Clone)]
+pub struct CachedActor {
+    pub document: String,
+    pub fetched: chrono::DateTime<chrono::Utc>,
+}
+
+/// A post which has been sent to followers
+#[derive(Debug, Clone)]
+pub struct BridgedPost {
+    pub post_id: u64,
+    pub project_id: u64,
+    /// Handle of the project when the post was bridged
+    pub handle: String,
+    pub published: chrono::DateTime<chrono::Utc>,
+    /// Hash of the content the post was last bridged with, to notice edits
+    pub content_hash: String,
+    /// When we found out the post was deleted, if it has been
+    pub deleted: Option<chrono::DateTime<chrono::Utc>>,
+    /// Page of the post on cohost, empty for posts bridged before it was kept
+    pub url: String,
+    /// When cohost first said the post doesn't exist, if it hasn't been
+    /// found again since
+    pub missing: Option<chrono::DateTime<chrono::Utc>>,
+    /// Whether the post was only sent to followers, as posts by private
+    /// projects are
+    pub private: bool,
+}
+
+/// Storage for everything the bridge needs to remember.
+/// Methods are synchronous since every backend is local and fast
+pub trait Store: Send + Sync {
+    /// PKCS#8 PEM private key of a project, if one has been generated
+    fn key(&self, project_id: u64) -> anyhow::Result<Option<String>>;
+    fn set_key(&self, project_id: u64, private_key_pem: &str) -> anyhow::Result<()>;
+    /// Number of projects which have been bridged, which is every project with a key
+    fn count_projects(&self) -> anyhow::Result<u64>;
+
+    /// Current handle of a project, if it has ever been bridged
+    fn project_handle(&self, project_id: u64) -> anyhow::Result<Option<String>>;
+    /// Record the current handle of a project, returning its previous handle
+    /// if it has been renamed. Previous handles are kept as former handles
+    fn set_project_handle(&self, project_id: u64, handle: &str) -> anyhow::Result<Option<String>>;
+    fn former_handles(&self, project_id: u64) -> anyhow::Result<Vec<String>>;
+    /// A project which used to have `handle`, so links to it can be followed
+    fn project_with_former_handle(&self, handle: &str) -> anyhow::Result<Option<u64>>;
+
+    /// Add or update a follower of a project
+    fn add_follower(&self, project_id: u64, follower: &Follower) -> anyhow::Result<()>;
+    /// Remove a follower, returning whether they were following
+    fn remove_follower(&self, project_id: u64, actor: &str) -> anyhow::Result<bool>;
+    fn followers(&self, project_id: u64) -> anyhow::Result<Vec<Follower>>;
+
+    /// Add or update a pending follow of a private project
+    fn add_follow_request(&self, project_id: u64, request: &FollowRequest) -> anyhow::Result<()>;
+    /// Remove a pending follow, returning whether there was one
+    fn remove_follow_request(&self, project_id: u64, actor: &str) -> anyhow::Result<bool>;
+    fn follow_requests(&self, project_id: u64) -> anyhow::Result<Vec<FollowRequest>>;
+    /// All projects with at least one follower, as `(project_id, handle)`
+    fn followed_projects(&self) -> anyhow::Result<Vec<(u64, String)>>;
+
+    /// ID of the newest post the poller has seen for a project
+    fn last_seen_post(&self, project_id: u64) -> anyhow::Result<Option<u64>>;
+    fn set_last_seen_post(&self, project_id: u64, post_id: u64) -> anyhow::Result<()>;
+
+    /// Record a post as bridged along with its normalized hashtags,
+    /// replacing any earlier record of it
+    fn record_post(&self, post: &BridgedPost, hashtags: &[String]) -> anyhow::Result<()>;
+    fn bridged_post(&self, post_id: u64) -> anyhow::Result<Option<BridgedPost>>;
+    /// Bridged posts of a project with an ID of at least `since_post_id`
+    /// which haven't been deleted
+    fn bridged_posts(
+        &self,
+        project_id: u64,
+        since_post_id: u64,
+    ) -> anyhow::Result<Vec<BridgedPost>>;
+    /// Mark a bridged post as deleted, which also removes it from hashtags
+    fn delete_post(
+        &self,
+        post_id: u64,
+        deleted: chrono::DateTime<chrono::Utc>,
+    ) -> anyhow::Result<()>;
+    /// Record when cohost first said a bridged post doesn't exist, or `None`
+    /// once it has been found again
+    fn set_post_missing(
+        &self,
+        post_id: u64,
+        missing: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> anyhow::Result<()>;
+    /// Bridged posts with a hashtag, newest first. Hashtags are case insensitive
+    fn posts_with_tag(
+        &self,
+        hashtag: &str,
+        offset: u64,
+        limit: u64,
+    ) -> anyhow::Result<Vec<BridgedPost>>;
+    fn count_posts_with_tag(&self, hashtag: &str) -> anyhow::Result<u64>;
+    /// Number of bridged posts which haven't been deleted
+    fn count_posts(&self) -> anyhow::Result<u64>;
+
+    /// Add a delivery or update an existing one with the same ID
+    fn save_delivery(&self, delivery: &Delivery) -> anyhow::Result<()>;
+    fn remove_delivery(&self, id: &str) -> anyhow::Result<()>;
+    fn deliveries(&self) -> anyhow::Result<Vec<Delivery>>;
+
+    fn cached_actor(&self, id: &str) -> anyhow::Result<Option<CachedActor>>;
+    fn cache_actor(&self, id: &str, actor: &CachedActor) -> anyhow::Result<()>;
+}
@@ -0,0 +1,539 @@
+use super::{legacy, BridgedPost, CachedActor, Store};
+use crate::activitypub::{
+    delivery::Delivery,
+    followers::{FollowRequest, Follower},
+};
+use anyhow::Context;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::info;
+
+/// Schema migrations, applied in order. The number applied so far is kept
+/// in SQLite's `user_version`, so never edit or reorder these, only append
+const MIGRATIONS: &[&str] = &[
+    include_str!("migrations/0001_initial.sql"),
+    include_str!("migrations/0002_bridged_posts.sql"),
+    include_str!("migrations/0003_content_hash.sql"),
+    include_str!("migrations/0004_deleted_posts.sql"),
+    include_str!("migrations/0005_follow_requests.sql"),
+    include_str!("migrations/0006_project_ids.sql"),
+    include_str!("migrations/0007_post_urls.sql"),
+    include_str!("migrations/0008_missing_posts.sql"),
+    include_str!("migrations/0009_private_posts.sql"),
+];
+
+/// Store backed by an SQLite database
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Open the database at `path`, creating it if needed. A new database
+    /// imports the files kept next to it by versions before the database
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        // The database holds private keys, so only we may read it. SQLite
+        // gives its journal files the same permissions
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(path)
+            .with_context(|| format!("failed to create database {:?}", path))?;
+        let connection = Connection::open(path)
+            .with_context(|| format!("failed to open database {:?}", path))?;
+        Self::with_connection(connection, path.parent())
+    }
+
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?, None)
+    }
+
+    fn with_connection(
+        mut connection: Connection,
+        data_dir: Option<&Path>,
+    ) -> anyhow::Result<Self> {
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut connection, data_dir)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+/// Apply any migrations the database doesn't have yet. Along with the first,
+/// import the files of the old store from `data_dir`
+fn migrate(connection: &mut Connection, data_dir: Option<&Path>) -> anyhow::Result<()> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        info!("applying database migration {}", index + 1);
+        let transaction = connection.transaction()?;
+        transaction
+            .execute_batch(migration)
+            .with_context(|| format!("failed to apply migration {}", index + 1))?;
+        if let (0, Some(data_dir)) = (index, data_dir) {
+            legacy::import(&transaction, data_dir)
+                .with_context(|| format!("failed to import old data from {:?}", data_dir))?;
+        }
+        transaction.pragma_update(None, "user_version", index + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+/// Read a [BridgedPost] from columns in the order of the `bridged_posts` table
+fn bridged_post_from_row(row: &rusqlite::Row) -> rusqlite::Result<BridgedPost> {
+    Ok(BridgedPost {
+        post_id: row.get::<_, i64>(0)? as u64,
+        project_id: row.get::<_, i64>(1)? as u64,
+        handle: row.get(2)?,
+        published: row.get(3)?,
+        content_hash: row.get(4)?,
+        deleted: row.get(5)?,
+        url: row.get(6)?,
+        missing: row.get(7)?,
+        private: row.get(8)?,
+    })
+}
+
+impl Store for SqliteStore {
+    fn key(&self, project_id: u64) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT private_key_pem FROM keys WHERE project_id = ?",
+                [project_id as i64],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn set_key(&self, project_id: u64, private_key_pem: &str) -> anyhow::Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO keys (project_id, private_key_pem) VALUES (?, ?)",
+            params![project_id as i64, private_key_pem],
+        )?;
+        Ok(())
+    }
+
+    fn count_projects(&self) -> anyhow::Result<u64> {
+        let count: i64 =
+            self.connection
+                .lock()
+                .unwrap()
+                .query_row("SELECT COUNT(*) FROM keys", [], |row| row.get(0))?;
+        Ok(count as u64)
+    }
+
+    fn project_handle(&self, project_id: u64) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT handle FROM projects WHERE project_id = ?",
+                [project_id as i64],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn set_project_handle(&self, project_id: u64, handle: &str) -> anyhow::Result<Option<String>> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let previous: Option<String> = transaction
+            .query_row(
+                "SELECT handle FROM projects WHERE project_id = ?",
+                [project_id as i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if previous.as_deref() == Some(handle) {
+            return Ok(None);
+        }
+        transaction.execute(
+            "INSERT OR REPLACE INTO projects (project_id, handle) VALUES (?, ?)",
+            params![project_id as i64, handle],
+        )?;
+        if let Some(previous) = &previous {
+            transaction.execute(
+                "INSERT OR IGNORE INTO former_handles (project_id, handle) VALUES (?, ?)",
+                params![project_id as i64, previous],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(previous)
+    }
+
+    fn former_handles(&self, project_id: u64) -> anyhow::Result<Vec<String>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached(
+            "SELECT handle FROM former_handles WHERE project_id = ? ORDER BY handle",
+        )?;
+        let handles = statement
+            .query_map([project_id as i64], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(handles)
+    }
+
+    fn project_with_former_handle(&self, handle: &str) -> anyhow::Result<Option<u64>> {
+        let project_id: Option<i64> = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT project_id FROM former_handles WHERE handle = ?
+                 ORDER BY project_id LIMIT 1",
+                [handle],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(project_id.map(|project_id| project_id as u64))
+    }
+
+    fn add_follower(&self, project_id: u64, follower: &Follower) -> anyhow::Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO followers
+             (project_id, actor, inbox, shared_inbox, legacy_handle)
+             VALUES (?, ?, ?, ?, ?)",
+            params![
+                project_id as i64,
+                &follower.actor,
+                &follower.inbox,
+                &follower.shared_inbox,
+                &follower.legacy_handle,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove_follower(&self, project_id: u64, actor: &str) -> anyhow::Result<bool> {
+        let removed = self.connection.lock().unwrap().execute(
+            "DELETE FROM followers WHERE project_id = ? AND actor = ?",
+            params![project_id as i64, actor],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn followers(&self, project_id: u64) -> anyhow::Result<Vec<Follower>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached(
+            "SELECT actor, inbox, shared_inbox, legacy_handle FROM followers
+             WHERE project_id = ? ORDER BY actor",
+        )?;
+        let followers = statement
+            .query_map([project_id as i64], |row| {
+                Ok(Follower {
+                    actor: row.get(0)?,
+                    inbox: row.get(1)?,
+                    shared_inbox: row.get(2)?,
+                    legacy_handle: row.get(3)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(followers)
+    }
+
+    fn add_follow_request(&self, project_id: u64, request: &FollowRequest) -> anyhow::Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO follow_requests
+             (project_id, actor, inbox, shared_inbox, activity, received)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            params![
+                project_id as i64,
+                &request.follower.actor,
+                &request.follower.inbox,
+                &request.follower.shared_inbox,
+                &request.activity,
+                &request.received,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove_follow_request(&self, project_id: u64, actor: &str) -> anyhow::Result<bool> {
+        let removed = self.connection.lock().unwrap().execute(
+            "DELETE FROM follow_requests WHERE project_id = ? AND actor = ?",
+            params![project_id as i64, actor],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn follow_requests(&self, project_id: u64) -> anyhow::Result<Vec<FollowRequest>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached(
+            "SELECT actor, inbox, shared_inbox, activity, received FROM follow_requests
+             WHERE project_id = ? ORDER BY received",
+        )?;
+        let requests = statement
+            .query_map([project_id as i64], |row| {
+                Ok(FollowRequest {
+                    follower: Follower {
+                        actor: row.get(0)?,
+                        inbox: row.get(1)?,
+                        shared_inbox: row.get(2)?,
+                        legacy_handle: None,
+                    },
+                    activity: row.get(3)?,
+                    received: row.get(4)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(requests)
+    }
+
+    fn followed_projects(&self) -> anyhow::Result<Vec<(u64, String)>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached(
+            "SELECT project_id, handle FROM projects
+             WHERE EXISTS (SELECT 1 FROM followers WHERE followers.project_id = projects.project_id)
+             ORDER BY project_id",
+        )?;
+        let projects = statement
+            .query_map([], |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(projects)
+    }
+
+    fn last_seen_post(&self, project_id: u64) -> anyhow::Result<Option<u64>> {
+        Ok(self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT post_id FROM seen_posts WHERE project_id = ?",
+                [project_id as i64],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?
+            .map(|post_id| post_id as u64))
+    }
+
+    fn set_last_seen_post(&self, project_id: u64, post_id: u64) -> anyhow::Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO seen_posts (project_id, post_id) VALUES (?, ?)",
+            params![project_id as i64, post_id as i64],
+        )?;
+        Ok(())
+    }
+
+    fn record_post(&self, post: &BridgedPost, hashtags: &[String]) -> anyhow::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO bridged_posts
+             (post_id, project_id, handle, published, content_hash, deleted, url, missing,
+              private)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                post.post_id as i64,
+                post.project_id as i64,
+                &post.handle,
+                &post.published,
+                &post.content_hash,
+                &post.deleted,
+                &post.url,
+                &post.missing,
+                post.private,
+            ],
+        )?;
+        transaction.execute(
+            "DELETE FROM post_tags WHERE post_id = ?",
+            [post.post_id as i64],
+        )?;
+        for hashtag in hashtags {
+            transaction.execute(
+                "INSERT OR IGNORE INTO post_tags (post_id, tag) VALUES (?, ?)",
+                params![post.post_id as i64, hashtag.to_lowercase()],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn bridged_post(&self, post_id: u64) -> anyhow::Result<Option<BridgedPost>> {
+        Ok(self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT post_id, project_id, handle, published, content_hash, deleted, url, missing,
+                private
+                 FROM bridged_posts WHERE post_id = ?",
+                [post_id as i64],
+                bridged_post_from_row,
+            )
+            .optional()?)
+    }
+
+    fn bridged_posts(
+        &self,
+        project_id: u64,
+        since_post_id: u64,
+    ) -> anyhow::Result<Vec<BridgedPost>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached(
+            "SELECT post_id, project_id, handle, published, content_hash, deleted, url, missing,
+                private
+             FROM bridged_posts
+             WHERE project_id = ? AND post_id >= ? AND deleted IS NULL
+             ORDER BY post_id",
+        )?;
+        let posts = statement
+            .query_map(
+                params![project_id as i64, since_post_id as i64],
+                bridged_post_from_row,
+            )?
+            .collect::<Result<_, _>>()?;
+        Ok(posts)
+    }
+
+    fn delete_post(
+        &self,
+        post_id: u64,
+        deleted: chrono::DateTime<chrono::Utc>,
+    ) -> anyhow::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "UPDATE bridged_posts SET deleted = ? WHERE post_id = ?",
+            params![&deleted, post_id as i64],
+        )?;
+        transaction.execute("DELETE FROM post_tags WHERE post_id = ?", [post_id as i64])?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn set_post_missing(
+        &self,
+        post_id: u64,
+        missing: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> anyhow::Result<()> {
+        self.connection.lock().unwrap().execute(
+            "UPDATE bridged_posts SET missing = ? WHERE post_id = ?",
+            params![&missing, post_id as i64],
+        )?;
+        Ok(())
+    }
+
+    fn posts_with_tag(
+        &self,
+        hashtag: &str,
+        offset: u64,
+        limit: u64,
+    ) -> anyhow::Result<Vec<BridgedPost>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached(
+            "SELECT bridged_posts.post_id, project_id, handle, published, content_hash, deleted, url, missing,
+                private
+             FROM bridged_posts JOIN post_tags ON post_tags.post_id = bridged_posts.post_id
+             WHERE post_tags.tag = ?
+             ORDER BY published DESC LIMIT ? OFFSET ?",
+        )?;
+        let posts = statement
+            .query_map(
+                params![hashtag.to_lowercase(), limit as i64, offset as i64],
+                bridged_post_from_row,
+            )?
+            .collect::<Result<_, _>>()?;
+        Ok(posts)
+    }
+
+    fn count_posts_with_tag(&self, hashtag: &str) -> anyhow::Result<u64> {
+        let count: i64 = self.connection.lock().unwrap().query_row(
+            "SELECT COUNT(*) FROM post_tags WHERE tag = ?",
+            [hashtag.to_lowercase()],
+            |row| row.get(0),
+        )?;
+        Ok(count as u64)
+    }
+
+    fn count_posts(&self) -> anyhow::Result<u64> {
+        let count: i64 = self.connection.lock().unwrap().query_row(
+            "SELECT COUNT(*) FROM bridged_posts WHERE deleted IS NULL",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(count as u64)
+    }
+
+    fn save_delivery(&self, delivery: &Delivery) -> anyhow::Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO deliveries
+             (id, project_id, actor, inbox, activity, created, attempts, next_attempt)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                &delivery.id,
+                delivery.project_id as i64,
+                &delivery.actor,
+                &delivery.inbox,
+                &delivery.activity,
+                &delivery.created,
+                delivery.attempts,
+                &delivery.next_attempt,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove_delivery(&self, id: &str) -> anyhow::Result<()> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM deliveries WHERE id = ?", [id])?;
+        Ok(())
+    }
+
+    fn deliveries(&self) -> anyhow::Result<Vec<Delivery>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached(
+            "SELECT id, project_id, actor, inbox, activity, created, attempts, next_attempt
+             FROM deliveries",
+        )?;
+        let deliveries = statement
+            .query_map([], |row| {
+                Ok(Delivery {
+                    id: row.get(0)?,
+                    project_id: row.get::<_, i64>(1)? as u64,
+                    actor: row.get(2)?,
+                    inbox: row.get(3)?,
+                    activity: row.get(4)?,
+                    created: row.get(5)?,
+                    attempts: row.get(6)?,
+                    next_attempt: row.get(7)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(deliveries)
+    }
+
+    fn cached_actor(&self, id: &str) -> anyhow::Result<Option<CachedActor>> {
+        Ok(self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT document, fetched FROM remote_actors WHERE id = ?",
+                [id],
+                |row| {
+                    Ok(CachedActor {
+                        document: row.get(0)?,
+                        fetched: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    fn cache_actor(&self, id: &str, actor: &CachedActor) -> anyhow::Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO remote_actors (id, document, fetched) VALUES (?, ?, ?)",
+            params![id, &actor.document, &actor.fetched],
+        )?;
+        Ok(())
+    }
+}
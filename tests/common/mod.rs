@@ -0,0 +1,717 @@
+//! Test harness: a fake cohost serving fixture data, and a bridge pointed at it
+
+#![allow(dead_code)]
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query},
+    response::{Html, IntoResponse},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use cobridge::activitypub::{
+    client::{FederationClient, RemoteKey},
+    delivery::DeliveryQueue,
+    error::ErrorWithStatus,
+    followers::Follower,
+    keys::{public_key_pem, KeyStore},
+    server::State,
+    signature::{digest_header, http_date, sign_request, verify_request, SignatureHeader},
+};
+use cobridge::cohost::CohostApi;
+use cobridge::consent::ConsentPolicy;
+use cobridge::store::{BridgedPost, MemoryStore, Store};
+use http::{header, HeaderMap, Method, Request, StatusCode, Uri};
+use hyper::{Body, Client};
+use rsa::RsaPrivateKey;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Domain the bridge under test believes it is served on
+pub const DOMAIN: &str = "bridge.test";
+
+/// Number of posts on each page of `posts.profilePosts`
+pub const PAGE_SIZE: usize = 20;
+
+/// A project known to the fake cohost
+#[derive(Clone)]
+pub struct FixtureProject {
+    pub project: Value,
+    /// Posts newest first, with pinned posts at the top like cohost returns them
+    pub posts: Vec<Value>,
+}
+
+/// Stand-in for cohost, serving projects from `tests/fixtures/cohost/<handle>/`.
+/// Clones share the same projects, so tests can change them while it runs
+#[derive(Clone, Default)]
+pub struct FakeCohost {
+    pub projects: Arc<Mutex<HashMap<String, FixtureProject>>>,
+    /// Whether pages fail to load, like when cohost is overloaded
+    pub pages_failing: Arc<AtomicBool>,
+    /// Number of tRPC queries made
+    pub trpc_queries: Arc<AtomicUsize>,
+}
+
+impl FakeCohost {
+    /// Load every project under `tests/fixtures/cohost`
+    pub fn from_fixtures() -> Self {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/cohost");
+        let mut projects = HashMap::new();
+        for entry in std::fs::read_dir(root).unwrap() {
+            let dir = entry.unwrap().path();
+            let handle = dir.file_name().unwrap().to_str().unwrap().to_string();
+            let read = |name: &str| -> Value {
+                serde_json::from_slice(&std::fs::read(dir.join(name)).unwrap()).unwrap()
+            };
+            let project = read("project.json");
+            let posts = match read("posts.json") {
+                Value::Array(posts) => posts,
+                _ => panic!("posts.json for {} is not an array", handle),
+            };
+            projects.insert(handle, FixtureProject { project, posts });
+        }
+        Self {
+            projects: Arc::new(Mutex::new(projects)),
+            pages_failing: Arc::default(),
+            trpc_queries: Arc::default(),
+        }
+    }
+
+    pub fn project(&self, handle: &str) -> Option<FixtureProject> {
+        self.projects.lock().unwrap().get(handle).cloned()
+    }
+
+    /// Change a project's profile, including the copies embedded in its posts
+    pub fn edit_project(&self, handle: &str, edit: impl FnOnce(&mut Value)) {
+        let mut projects = self.projects.lock().unwrap();
+        let fixture = projects.get_mut(handle).unwrap();
+        edit(&mut fixture.project);
+        for post in &mut fixture.posts {
+            if post["postingProject"]["projectId"] == fixture.project["projectId"] {
+                post["postingProject"] = fixture.project.clone();
+            }
+        }
+    }
+
+    /// Change a project's handle, as its author can at any time. Cohost
+    /// forgets the old handle straight away
+    pub fn rename_project(&self, handle: &str, new_handle: &str) {
+        self.edit_project(handle, |project| project["handle"] = json!(new_handle));
+        let mut projects = self.projects.lock().unwrap();
+        let fixture = projects.remove(handle).unwrap();
+        projects.insert(new_handle.to_string(), fixture);
+    }
+
+    /// Make project and post pages fail with an error other than not found
+    pub fn set_pages_failing(&self, failing: bool) {
+        self.pages_failing.store(failing, Ordering::SeqCst);
+    }
+
+    fn page_failure(&self) -> Option<Value> {
+        self.pages_failing.load(Ordering::SeqCst).then(|| {
+            json!({
+                "error": {
+                    "message": "internal server error",
+                    "errorCode": "internal-server-error",
+                }
+            })
+        })
+    }
+
+    /// Change a project's posts, like its author posting or editing
+    pub fn edit_posts(&self, handle: &str, edit: impl FnOnce(&mut Vec<Value>)) {
+        edit(&mut self.projects.lock().unwrap().get_mut(handle).unwrap().posts)
+    }
+
+    fn find_post(&self, post_id: u64) -> Option<(FixtureProject, Value)> {
+        self.projects.lock().unwrap().values().find_map(|project| {
+            project
+                .posts
+                .iter()
+                .find(|post| post["postId"] == post_id)
+                .map(|post| (project.clone(), post.clone()))
+        })
+    }
+
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/api/v1/trpc/:queries", get(handle_trpc))
+            .route("/:handle", get(handle_project_page))
+            .route("/:handle/post/:post", get(handle_post_page))
+            .layer(Extension(self))
+    }
+
+    /// Serve on an ephemeral loopback port
+    pub fn spawn(self) -> SocketAddr {
+        spawn(self.router())
+    }
+}
+
+/// Page carrying loader state the way cohost embeds it
+fn loader_state_page(state: Value) -> Html<String> {
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><title>cohost!</title></head>
+<body>
+<div id="app"></div>
+<script type="application/json" id="__COHOST_LOADER_STATE__">{}</script>
+</body>
+</html>
+"#,
+        state
+    ))
+}
+
+fn loader_error() -> Value {
+    json!({
+        "error": {
+            "message": "not found",
+            "errorCode": "not-found",
+        }
+    })
+}
+
+async fn handle_project_page(
+    Path(handle): Path<String>,
+    Extension(cohost): Extension<FakeCohost>,
+) -> Html<String> {
+    if let Some(failure) = cohost.page_failure() {
+        return loader_state_page(failure);
+    }
+    loader_state_page(match cohost.project(&handle) {
+        Some(fixture) => json!({
+            "project-page-view": {
+                "project": fixture.project,
+                "pageHandle": handle,
+                "canAccessPermissions": {
+                    "canRead": "allowed",
+                    "canInteract": "allowed",
+                    "canShare": "allowed",
+                    "canEdit": "not-allowed",
+                },
+            }
+        }),
+        None => loader_error(),
+    })
+}
+
+async fn handle_post_page(
+    Path((_handle, post)): Path<(String, String)>,
+    Extension(cohost): Extension<FakeCohost>,
+) -> Html<String> {
+    if let Some(failure) = cohost.page_failure() {
+        return loader_state_page(failure);
+    }
+    // Post URLs may carry a slug after the ID, like `1234-some-title`. Like
+    // cohost, the handle isn't checked so the post is found under any project
+    let post_id = post.split('-').next().and_then(|id| id.parse::<u64>().ok());
+    let found = post_id.and_then(|post_id| cohost.find_post(post_id));
+    loader_state_page(match found {
+        Some((project, post)) => json!({
+            "single-post-view": {
+                "postId": post["postId"],
+                "project": project.project,
+                "post": post,
+            }
+        }),
+        None => loader_error(),
+    })
+}
+
+#[derive(Deserialize)]
+struct TrpcQuery {
+    input: String,
+}
+
+fn trpc_error(path: &str, code: &str, http_status: u16) -> Value {
+    json!({
+        "error": {
+            "code": -32004,
+            "data": {
+                "code": code,
+                "httpStatus": http_status,
+                "path": path,
+                "stack": "",
+            },
+            "message": code,
+        }
+    })
+}
+
+async fn handle_trpc(
+    Path(queries): Path<String>,
+    Query(query): Query<TrpcQuery>,
+    Extension(cohost): Extension<FakeCohost>,
+) -> impl IntoResponse {
+    let input: Value = serde_json::from_str(&query.input).unwrap();
+    cohost
+        .trpc_queries
+        .fetch_add(queries.split(',').count(), Ordering::SeqCst);
+    let responses: Vec<Value> = queries
+        .split(',')
+        .enumerate()
+        .map(|(i, name)| {
+            let input = &input[i.to_string()];
+            match name {
+                "posts.profilePosts" => profile_posts(&cohost, input),
+                _ => trpc_error(name, "NOT_FOUND", 404),
+            }
+        })
+        .collect();
+    Json(responses)
+}
+
+fn profile_posts(cohost: &FakeCohost, input: &Value) -> Value {
+    let fixture = match input["projectHandle"]
+        .as_str()
+        .and_then(|handle| cohost.project(handle))
+    {
+        Some(fixture) => fixture,
+        None => return trpc_error("posts.profilePosts", "NOT_FOUND", 404),
+    };
+    let page = input["page"].as_u64().unwrap_or(0) as usize;
+    let posts: Vec<&Value> = fixture
+        .posts
+        .iter()
+        .skip(page * PAGE_SIZE)
+        .take(PAGE_SIZE)
+        .collect();
+    json!({
+        "result": {
+            "data": {
+                "posts": posts,
+                "pagination": {
+                    "currentPage": page,
+                    "nextPage": page + 1,
+                    "morePagesForward": fixture.posts.len() > (page + 1) * PAGE_SIZE,
+                },
+            }
+        }
+    })
+}
+
+/// Serve a router on an ephemeral loopback port
+pub fn spawn(app: Router) -> SocketAddr {
+    let server =
+        axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(app.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+/// Where a response redirects to, if it redirects to another of the bridge's URLs
+fn local_redirect(response: &Response) -> Option<String> {
+    if !response.status.is_redirection() {
+        return None;
+    }
+    let location = response.headers.get(header::LOCATION)?.to_str().ok()?;
+    location
+        .starts_with(&format!("https://{}/", DOMAIN))
+        .then(|| location.to_string())
+}
+
+/// A running bridge and the fake cohost behind it
+pub struct TestBridge {
+    pub addr: SocketAddr,
+    pub state: Arc<State>,
+}
+
+impl TestBridge {
+    /// Spawn a bridge which only bridges projects with the default opt-in marker
+    pub fn spawn(cohost: FakeCohost) -> Self {
+        Self::spawn_with_consent(cohost, ConsentPolicy::new("#cobridge", &[]))
+    }
+
+    pub fn spawn_with_consent(cohost: FakeCohost, consent: ConsentPolicy) -> Self {
+        Self::spawn_with(cohost, consent, vec![])
+    }
+
+    /// Spawn a bridge which lets `instance_actors` see private posts
+    pub fn spawn_with(
+        cohost: FakeCohost,
+        consent: ConsentPolicy,
+        instance_actors: Vec<String>,
+    ) -> Self {
+        let cohost_addr = cohost.spawn();
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let state = Arc::new(State {
+            store: store.clone(),
+            api: CohostApi::with_base_url(format!("http://{}", cohost_addr).parse().unwrap())
+                .unwrap(),
+            domain: DOMAIN.to_string(),
+            keys: KeyStore::new(store.clone()),
+            client: FederationClient::new(DOMAIN),
+            delivery: DeliveryQueue::new(store),
+            consent,
+            adult_content_warning: "18+".to_string(),
+            clock_skew: chrono::Duration::hours(1),
+            instance_actors,
+        });
+        let addr = spawn(cobridge::app(state.clone()));
+        Self { addr, state }
+    }
+
+    /// Where to send requests for a path or one of the bridge's own
+    /// `https://bridge.test/...` URLs
+    pub fn local_uri(&self, url: &str) -> Uri {
+        let path = url
+            .strip_prefix(&format!("https://{}", DOMAIN))
+            .unwrap_or(url);
+        format!("http://{}{}", self.addr, path).parse().unwrap()
+    }
+
+    pub async fn send(&self, request: Request<Body>) -> Response {
+        let response = Client::new().request(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        Response {
+            status,
+            headers,
+            body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+        }
+    }
+
+    pub async fn get(&self, url: &str, accept: &str) -> Response {
+        self.send(
+            Request::get(self.local_uri(url))
+                .header(header::ACCEPT, accept)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+    }
+
+    /// Fetch as a fediverse server would, following redirects from aliases
+    pub async fn get_activity(&self, url: &str) -> Response {
+        let mut response = self.get(url, "application/activity+json").await;
+        while let Some(location) = local_redirect(&response) {
+            response = self.get(&location, "application/activity+json").await;
+        }
+        response
+    }
+
+    /// Fetch as a fediverse server would, signed by a remote actor
+    pub async fn get_signed(&self, url: &str, actor: &RemoteActor) -> Response {
+        let mut response = self.get_signed_once(url, actor).await;
+        while let Some(location) = local_redirect(&response) {
+            response = self.get_signed_once(&location, actor).await;
+        }
+        response
+    }
+
+    async fn get_signed_once(&self, url: &str, actor: &RemoteActor) -> Response {
+        let uri = self.local_uri(url);
+        let mut request = Request::get(uri.clone())
+            .header(header::ACCEPT, "application/activity+json")
+            .body(Body::empty())
+            .unwrap();
+        request
+            .headers_mut()
+            .extend(actor.sign(&Method::GET, &uri, None));
+        self.send(request).await
+    }
+
+    /// Deliver an activity from a remote actor
+    pub async fn post_signed(&self, url: &str, actor: &RemoteActor, activity: &Value) -> Response {
+        let uri = self.local_uri(url);
+        let body = activity.to_string();
+        let mut request = Request::post(uri.clone())
+            .header(header::CONTENT_TYPE, "application/activity+json")
+            .body(Body::from(body.clone()))
+            .unwrap();
+        request
+            .headers_mut()
+            .extend(actor.sign(&Method::POST, &uri, Some(body.as_bytes())));
+        self.send(request).await
+    }
+
+    /// Add a follower directly to the store, skipping the inbox
+    pub fn add_follower(&self, project_id: u64, handle: &str, actor: &str) {
+        self.state
+            .store
+            .set_project_handle(project_id, handle)
+            .unwrap();
+        self.state
+            .store
+            .add_follower(
+                project_id,
+                &Follower {
+                    actor: actor.to_string(),
+                    inbox: format!("{}/inbox", actor),
+                    shared_inbox: None,
+                    legacy_handle: None,
+                },
+            )
+            .unwrap();
+    }
+
+    /// Record a post as bridged, as the poller does when sending it to followers
+    pub fn record_post(&self, post: &BridgedPost, hashtags: &[&str]) {
+        let hashtags: Vec<String> = hashtags.iter().map(|tag| tag.to_string()).collect();
+        self.state.store.record_post(post, &hashtags).unwrap();
+    }
+
+    /// Check the signature on a request the bridge sent as a remote server
+    /// would: by fetching the actor its key ID names and checking the key
+    /// that actor document holds
+    pub async fn verify_sent(
+        &self,
+        request: &ReceivedRequest,
+    ) -> Result<RemoteKey, ErrorWithStatus> {
+        let signature: SignatureHeader = request
+            .headers
+            .get("signature")
+            .expect("request is not signed")
+            .to_str()
+            .unwrap()
+            .parse()?;
+        let actor = signature.key_id.split('#').next().unwrap();
+        let document = self.get_activity(actor).await;
+        assert_eq!(document.status, StatusCode::OK, "fetching {}", actor);
+
+        // The bridge can't fetch its own URLs, so give it the document
+        let store = MemoryStore::new();
+        store
+            .cache_actor(
+                actor,
+                &cobridge::store::CachedActor {
+                    document: document.body.to_string(),
+                    fetched: chrono::Utc::now(),
+                },
+            )
+            .unwrap();
+        verify_request(
+            &self.state.client,
+            &store,
+            &Method::POST,
+            &request.uri,
+            &request.headers,
+            &request.body,
+            chrono::Duration::minutes(5),
+        )
+        .await
+    }
+
+    /// Remove every queued delivery, returning their activities
+    pub fn take_deliveries(&self) -> Vec<Value> {
+        let mut deliveries = self.state.store.deliveries().unwrap();
+        deliveries.sort_by_key(|delivery| delivery.created);
+        deliveries
+            .into_iter()
+            .map(|delivery| {
+                self.state.store.remove_delivery(&delivery.id).unwrap();
+                serde_json::from_str(&delivery.activity).unwrap()
+            })
+            .collect()
+    }
+}
+
+/// A public post as the poller records it when bridging it
+pub fn bridged_post(project_id: u64, handle: &str, post_id: u64) -> BridgedPost {
+    BridgedPost {
+        post_id,
+        project_id,
+        handle: handle.to_string(),
+        published: chrono::Utc::now(),
+        content_hash: String::new(),
+        deleted: None,
+        url: String::new(),
+        missing: None,
+        private: false,
+    }
+}
+
+/// An actor on another server, which can sign requests to the bridge
+pub struct RemoteActor {
+    pub id: String,
+    pub key_id: String,
+    key: RsaPrivateKey,
+}
+
+impl RemoteActor {
+    /// Serve an actor document for `name` on its own loopback port
+    pub fn spawn(name: &str) -> Self {
+        Self::spawn_on_one_server(&[name]).remove(0)
+    }
+
+    /// Serve an actor document for `name`, changed by `edit` before it is served
+    pub fn spawn_with_document(name: &str, edit: impl Fn(&mut Value)) -> Self {
+        Self::spawn_actors(&[name], edit).remove(0)
+    }
+
+    /// Serve actor documents for each of `names` on the same loopback port
+    pub fn spawn_on_one_server(names: &[&str]) -> Vec<Self> {
+        Self::spawn_actors(names, |_| {})
+    }
+
+    fn spawn_actors(names: &[&str], edit: impl Fn(&mut Value)) -> Vec<Self> {
+        let listener = std::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let server = listener.local_addr().unwrap();
+        let mut app = Router::new();
+        let mut actors = vec![];
+        for name in names {
+            let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+            let public_key_pem = public_key_pem(&key).unwrap();
+            let id = format!("http://{}/users/{}", server, name);
+            let key_id = format!("{}#main-key", id);
+            let mut document = json!({
+                "@context": [
+                    "https://www.w3.org/ns/activitystreams",
+                    "https://w3id.org/security/v1",
+                ],
+                "id": id,
+                "type": "Person",
+                "preferredUsername": name,
+                "inbox": format!("{}/inbox", id),
+                "publicKey": {
+                    "id": key_id,
+                    "owner": id,
+                    "publicKeyPem": public_key_pem,
+                },
+            });
+            edit(&mut document);
+            app = app.route(
+                &format!("/users/{}", name),
+                get(move || async move { Json(document) }),
+            );
+            actors.push(Self { id, key_id, key });
+        }
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        actors
+    }
+
+    /// Headers for a signed request, including the signature
+    pub fn sign(&self, method: &Method, uri: &Uri, body: Option<&[u8]>) -> HeaderMap {
+        self.sign_at(method, uri, body, chrono::Utc::now())
+    }
+
+    /// Headers for a request signed as if it was sent at `date`
+    pub fn sign_at(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        body: Option<&[u8]>,
+        date: chrono::DateTime<chrono::Utc>,
+    ) -> HeaderMap {
+        let host = uri.authority().unwrap().to_string();
+        let date = http_date(date);
+        let mut signed = vec![("host", host.clone()), ("date", date)];
+        if let Some(body) = body {
+            signed.push(("digest", digest_header(body)));
+        }
+        let signature = sign_request(
+            &self.key_id,
+            &self.key,
+            method,
+            uri,
+            &signed
+                .iter()
+                .map(|(name, value)| (*name, value.as_str()))
+                .collect::<Vec<_>>(),
+        );
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in signed {
+            headers.insert(
+                header::HeaderName::from_static(name),
+                value.parse().unwrap(),
+            );
+        }
+        headers.insert("signature", signature.parse().unwrap());
+        headers
+    }
+
+    /// This actor signing with its own key while claiming it is `key_id`
+    pub fn claiming_key(&self, key_id: &str) -> Self {
+        Self {
+            id: self.id.clone(),
+            key_id: key_id.to_string(),
+            key: self.key.clone(),
+        }
+    }
+}
+
+/// A request received by a [MockInbox]
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl ReceivedRequest {
+    /// Body parsed as JSON
+    pub fn activity(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+}
+
+#[derive(Default)]
+struct MockInboxState {
+    received: Vec<ReceivedRequest>,
+    /// Statuses to respond with, in order. Once used up every request is accepted
+    statuses: VecDeque<StatusCode>,
+}
+
+/// A remote server's inboxes, which record every request they receive
+#[derive(Clone)]
+pub struct MockInbox {
+    pub addr: SocketAddr,
+    state: Arc<Mutex<MockInboxState>>,
+}
+
+impl MockInbox {
+    pub fn spawn() -> Self {
+        let state = Arc::new(Mutex::new(MockInboxState::default()));
+        let app = Router::new()
+            .route("/*path", post(Self::receive))
+            .layer(Extension(state.clone()));
+        Self {
+            addr: spawn(app),
+            state,
+        }
+    }
+
+    async fn receive(
+        uri: Uri,
+        headers: HeaderMap,
+        body: Bytes,
+        Extension(state): Extension<Arc<Mutex<MockInboxState>>>,
+    ) -> StatusCode {
+        let mut state = state.lock().unwrap();
+        state.received.push(ReceivedRequest { uri, headers, body });
+        state.statuses.pop_front().unwrap_or(StatusCode::ACCEPTED)
+    }
+
+    /// URL of an inbox at `path` on this server
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// Respond to the next requests with these statuses
+    pub fn respond_with(&self, statuses: &[StatusCode]) {
+        self.state.lock().unwrap().statuses.extend(statuses);
+    }
+
+    /// Remove every request received so far, returning them
+    pub fn take_received(&self) -> Vec<ReceivedRequest> {
+        std::mem::take(&mut self.state.lock().unwrap().received)
+    }
+}
+
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// Body parsed as JSON, or null if it isn't
+    pub body: Value,
+}
@@ -0,0 +1,173 @@
+//! Tests of which projects the bridge agrees to bridge
+
+mod common;
+
+use cobridge::consent::ConsentPolicy;
+use cobridge::poller::check_consent;
+use common::{bridged_post, FakeCohost, TestBridge};
+use http::StatusCode;
+use serde_json::json;
+
+const PROJECT_ID: u64 = 12345;
+const HANDLE: &str = "example-artist";
+
+#[tokio::test]
+async fn projects_without_the_marker_are_not_bridged() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+
+    let webfinger = bridge
+        .get(
+            "/.well-known/webfinger?resource=acct:unbridged-artist@bridge.test",
+            "application/jrd+json",
+        )
+        .await;
+    assert_eq!(webfinger.status, StatusCode::NOT_FOUND);
+    let actor = bridge.get_activity("/users/unbridged-artist").await;
+    assert_eq!(actor.status, StatusCode::NOT_FOUND);
+    let post = bridge
+        .get_activity("/users/unbridged-artist/posts/3001")
+        .await;
+    assert_eq!(post.status, StatusCode::NOT_FOUND);
+
+    // The marker is matched case insensitively, in the headline too
+    let cohost = FakeCohost::from_fixtures();
+    cohost.edit_project("unbridged-artist", |project| {
+        project["dek"] = json!("fine to follow from mastodon #CoBridge");
+    });
+    let bridge = TestBridge::spawn(cohost);
+    let actor = bridge.get_activity("/users/unbridged-artist").await;
+    assert_eq!(actor.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn allowed_projects_are_bridged() {
+    let bridge = TestBridge::spawn_with_consent(
+        FakeCohost::from_fixtures(),
+        ConsentPolicy::new("", &[13579]),
+    );
+    bridge.record_post(&bridged_post(13579, "unbridged-artist", 3001), &[]);
+
+    let actor = bridge.get_activity("/users/unbridged-artist").await;
+    assert_eq!(actor.status, StatusCode::OK);
+    let post = bridge
+        .get_activity("/users/unbridged-artist/posts/3001")
+        .await;
+    assert_eq!(post.status, StatusCode::OK);
+
+    // With no marker, only allowed projects are bridged
+    let actor = bridge.get_activity("/users/example-artist").await;
+    assert_eq!(actor.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn allowed_projects_stay_allowed_when_renamed() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge =
+        TestBridge::spawn_with_consent(cohost.clone(), ConsentPolicy::new("", &[PROJECT_ID]));
+    bridge.add_follower(PROJECT_ID, HANDLE, "https://remote.test/users/alice");
+    bridge
+        .state
+        .store
+        .set_last_seen_post(PROJECT_ID, 1001)
+        .unwrap();
+
+    cohost.rename_project(HANDLE, "renamed-artist");
+    assert!(check_consent(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap());
+    assert_eq!(bridge.state.store.followers(PROJECT_ID).unwrap().len(), 1);
+    assert_eq!(
+        bridge.state.store.project_handle(PROJECT_ID).unwrap(),
+        Some("renamed-artist".to_string())
+    );
+
+    // The project which takes the old handle isn't allowed along with it
+    cohost.rename_project("unbridged-artist", HANDLE);
+    let actor = bridge.get_activity("/users/example-artist").await;
+    assert_eq!(actor.status, StatusCode::NOT_FOUND);
+    let actor = bridge.get_activity("/projects/12345").await;
+    assert_eq!(actor.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn projects_which_opt_out_are_deleted() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge = TestBridge::spawn(cohost.clone());
+    bridge.add_follower(PROJECT_ID, HANDLE, "https://remote.test/users/alice");
+    bridge.record_post(&bridged_post(PROJECT_ID, HANDLE, 1001), &["birds"]);
+
+    assert!(check_consent(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap());
+    assert!(bridge.take_deliveries().is_empty());
+
+    cohost.edit_project(HANDLE, |project| {
+        project["description"] = json!("Hi! I draw **birds** and sometimes other animals.");
+    });
+    assert!(!check_consent(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap());
+    let deliveries = bridge.take_deliveries();
+    assert_eq!(deliveries.len(), 1);
+    let delete = &deliveries[0];
+    assert_eq!(delete["type"], "Delete");
+    assert_eq!(delete["actor"], "https://bridge.test/projects/12345");
+    assert_eq!(delete["object"], "https://bridge.test/projects/12345");
+    assert_eq!(
+        delete["cc"],
+        json!(["https://bridge.test/projects/12345/followers"])
+    );
+
+    assert!(bridge.state.store.followers(PROJECT_ID).unwrap().is_empty());
+    let actor = bridge.get_activity("/users/example-artist").await;
+    assert_eq!(actor.status, StatusCode::NOT_FOUND);
+    let post = bridge
+        .get_activity("/users/example-artist/posts/1001")
+        .await;
+    assert_eq!(post.status, StatusCode::NOT_FOUND);
+
+    // Its posts are no longer counted anywhere
+    let tag = bridge.get_activity("/tags/birds").await;
+    assert_eq!(tag.body["totalItems"], 0);
+    let nodeinfo = bridge.get("/nodeinfo/2.1", "application/json").await;
+    assert_eq!(nodeinfo.body["usage"]["localPosts"], 0);
+}
+
+#[tokio::test]
+async fn consent_stands_when_it_cannot_be_checked() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge = TestBridge::spawn(cohost.clone());
+    bridge.add_follower(PROJECT_ID, HANDLE, "https://remote.test/users/alice");
+
+    // Cohost failing to load the project isn't opting out
+    cohost.set_pages_failing(true);
+    assert!(check_consent(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .is_err());
+    cohost.set_pages_failing(false);
+
+    // Nor is a project we can't find after a rename
+    cohost.rename_project(HANDLE, "renamed-artist");
+    assert!(check_consent(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .is_err());
+    assert!(bridge.take_deliveries().is_empty());
+    assert_eq!(bridge.state.store.followers(PROJECT_ID).unwrap().len(), 1);
+
+    // If it can be found through its posts, it's checked under its new handle
+    bridge
+        .state
+        .store
+        .set_last_seen_post(PROJECT_ID, 1001)
+        .unwrap();
+    assert!(check_consent(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap());
+    cohost.edit_project("renamed-artist", |project| {
+        project["description"] = json!("Hi! I draw **birds** and sometimes other animals.");
+    });
+    assert!(!check_consent(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap());
+    assert!(bridge.state.store.followers(PROJECT_ID).unwrap().is_empty());
+}
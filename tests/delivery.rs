@@ -0,0 +1,272 @@
+//! Tests of sending queued activities to remote inboxes
+
+mod common;
+
+use cobridge::activitypub::{
+    delivery::{send_due, Delivery, DeliveryConfig, INITIAL_RETRY_DELAY, MAX_RETRY_DELAY},
+    followers::Follower,
+};
+use common::{FakeCohost, MockInbox, TestBridge};
+use http::StatusCode;
+use serde_json::json;
+use std::time::Duration;
+
+const PROJECT_ID: u64 = 12345;
+const ACTOR: &str = "https://bridge.test/projects/12345";
+
+fn config() -> DeliveryConfig {
+    DeliveryConfig {
+        timeout: Duration::from_secs(5),
+        max_age: Duration::from_secs(24 * 60 * 60),
+    }
+}
+
+fn note_activity() -> serde_json::Value {
+    json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": "https://bridge.test/projects/12345/posts/1001/activity",
+        "type": "Create",
+        "actor": ACTOR,
+        "object": "https://bridge.test/projects/12345/posts/1001",
+    })
+}
+
+/// A bridge which knows the project sending the activities, as it would
+/// from its followers
+fn spawn_bridge() -> TestBridge {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+    bridge
+        .state
+        .store
+        .set_project_handle(PROJECT_ID, "example-artist")
+        .unwrap();
+    bridge
+}
+
+fn queued(bridge: &TestBridge) -> Vec<Delivery> {
+    bridge.state.store.deliveries().unwrap()
+}
+
+/// Make every queued delivery due now
+fn make_due(bridge: &TestBridge) {
+    for mut delivery in queued(bridge) {
+        delivery.next_attempt = chrono::Utc::now();
+        bridge.state.store.save_delivery(&delivery).unwrap();
+    }
+}
+
+/// Time until a queued delivery is next attempted
+fn delay(delivery: &Delivery) -> Duration {
+    (delivery.next_attempt - chrono::Utc::now())
+        .to_std()
+        .unwrap()
+}
+
+fn assert_about(actual: Duration, expected: Duration) {
+    assert!(
+        actual <= expected && actual + Duration::from_secs(10) >= expected,
+        "expected about {:?}, got {:?}",
+        expected,
+        actual
+    );
+}
+
+#[tokio::test]
+async fn deliveries_are_signed_by_the_actor() {
+    let bridge = spawn_bridge();
+    let inbox = MockInbox::spawn();
+
+    bridge
+        .state
+        .delivery
+        .deliver(
+            PROJECT_ID,
+            ACTOR,
+            &inbox.url("/users/alice/inbox"),
+            &note_activity(),
+        )
+        .unwrap();
+    send_due(&bridge.state, &config()).await;
+    assert!(queued(&bridge).is_empty());
+
+    let received = inbox.take_received();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].uri.path(), "/users/alice/inbox");
+    assert_eq!(received[0].activity(), note_activity());
+    assert_eq!(
+        received[0].headers["content-type"],
+        "application/activity+json"
+    );
+    let key = bridge.verify_sent(&received[0]).await.unwrap();
+    assert_eq!(key.id, "https://bridge.test/projects/12345#main-key");
+    assert_eq!(key.owner, ACTOR);
+
+    // A changed body no longer matches the signature
+    let mut tampered = received[0].clone();
+    tampered.body = b"{}".to_vec().into();
+    assert_eq!(
+        bridge.verify_sent(&tampered).await.unwrap_err().status,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn followers_on_one_server_share_a_delivery() {
+    let bridge = spawn_bridge();
+    let server = MockInbox::spawn();
+    let other_server = MockInbox::spawn();
+    let followers = [
+        (server.url("/users/alice"), Some(server.url("/inbox"))),
+        (server.url("/users/bob"), Some(server.url("/inbox"))),
+        (other_server.url("/users/carol"), None),
+    ];
+    for (actor, shared_inbox) in followers {
+        let follower = Follower {
+            inbox: format!("{}/inbox", actor),
+            actor,
+            shared_inbox,
+            legacy_handle: None,
+        };
+        bridge
+            .state
+            .store
+            .add_follower(PROJECT_ID, &follower)
+            .unwrap();
+    }
+
+    bridge
+        .state
+        .delivery
+        .deliver_to_followers(PROJECT_ID, ACTOR, &note_activity())
+        .unwrap();
+    send_due(&bridge.state, &config()).await;
+
+    let received = server.take_received();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].uri.path(), "/inbox");
+    let received = other_server.take_received();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].uri.path(), "/users/carol/inbox");
+    bridge.verify_sent(&received[0]).await.unwrap();
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_with_backoff() {
+    let bridge = spawn_bridge();
+    let inbox = MockInbox::spawn();
+    inbox.respond_with(&[
+        StatusCode::SERVICE_UNAVAILABLE,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        StatusCode::TOO_MANY_REQUESTS,
+    ]);
+
+    bridge
+        .state
+        .delivery
+        .deliver(PROJECT_ID, ACTOR, &inbox.url("/inbox"), &note_activity())
+        .unwrap();
+    send_due(&bridge.state, &config()).await;
+    let delivery = &queued(&bridge)[0];
+    assert_eq!(delivery.attempts, 1);
+    assert_about(delay(delivery), INITIAL_RETRY_DELAY);
+
+    // Nothing is sent until the retry is due
+    send_due(&bridge.state, &config()).await;
+    assert_eq!(inbox.take_received().len(), 1);
+
+    // Each retry waits twice as long as the one before
+    make_due(&bridge);
+    send_due(&bridge.state, &config()).await;
+    let delivery = &queued(&bridge)[0];
+    assert_eq!(delivery.attempts, 2);
+    assert_about(delay(delivery), INITIAL_RETRY_DELAY * 2);
+
+    make_due(&bridge);
+    send_due(&bridge.state, &config()).await;
+    assert_about(delay(&queued(&bridge)[0]), INITIAL_RETRY_DELAY * 4);
+
+    // Until the inbox accepts the same activity
+    make_due(&bridge);
+    send_due(&bridge.state, &config()).await;
+    assert!(queued(&bridge).is_empty());
+    let received = inbox.take_received();
+    assert_eq!(received.len(), 3);
+    for request in &received {
+        assert_eq!(request.activity(), note_activity());
+        bridge.verify_sent(request).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn retries_wait_at_most_the_maximum_delay() {
+    let bridge = spawn_bridge();
+    let inbox = MockInbox::spawn();
+    inbox.respond_with(&[StatusCode::BAD_GATEWAY; 2]);
+
+    bridge
+        .state
+        .delivery
+        .deliver(PROJECT_ID, ACTOR, &inbox.url("/inbox"), &note_activity())
+        .unwrap();
+    let mut delivery = queued(&bridge).remove(0);
+    delivery.attempts = 12;
+    bridge.state.store.save_delivery(&delivery).unwrap();
+    send_due(&bridge.state, &config()).await;
+    assert_about(delay(&queued(&bridge)[0]), MAX_RETRY_DELAY);
+
+    // Even after so many attempts that doubling would overflow
+    let mut delivery = queued(&bridge).remove(0);
+    delivery.attempts = 100;
+    delivery.next_attempt = chrono::Utc::now();
+    bridge.state.store.save_delivery(&delivery).unwrap();
+    send_due(&bridge.state, &config()).await;
+    assert_about(delay(&queued(&bridge)[0]), MAX_RETRY_DELAY);
+}
+
+#[tokio::test]
+async fn old_or_rejected_deliveries_are_given_up() {
+    let bridge = spawn_bridge();
+    let inbox = MockInbox::spawn();
+
+    // A delivery which keeps failing is dropped once it's older than the maximum age
+    inbox.respond_with(&[StatusCode::SERVICE_UNAVAILABLE; 2]);
+    bridge
+        .state
+        .delivery
+        .deliver(PROJECT_ID, ACTOR, &inbox.url("/inbox"), &note_activity())
+        .unwrap();
+    send_due(&bridge.state, &config()).await;
+    assert_eq!(queued(&bridge).len(), 1);
+    let mut delivery = queued(&bridge).remove(0);
+    delivery.created = chrono::Utc::now() - chrono::Duration::hours(25);
+    delivery.next_attempt = chrono::Utc::now();
+    bridge.state.store.save_delivery(&delivery).unwrap();
+    send_due(&bridge.state, &config()).await;
+    assert!(queued(&bridge).is_empty());
+    assert_eq!(inbox.take_received().len(), 2);
+
+    // Client errors won't change on a retry
+    inbox.respond_with(&[StatusCode::GONE]);
+    bridge
+        .state
+        .delivery
+        .deliver(PROJECT_ID, ACTOR, &inbox.url("/inbox"), &note_activity())
+        .unwrap();
+    send_due(&bridge.state, &config()).await;
+    assert!(queued(&bridge).is_empty());
+    assert_eq!(inbox.take_received().len(), 1);
+
+    // Unreachable servers are retried like server errors
+    bridge
+        .state
+        .delivery
+        .deliver(
+            PROJECT_ID,
+            ACTOR,
+            "http://127.0.0.1:1/inbox",
+            &note_activity(),
+        )
+        .unwrap();
+    send_due(&bridge.state, &config()).await;
+    assert_eq!(queued(&bridge)[0].attempts, 1);
+}
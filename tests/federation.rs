@@ -0,0 +1,292 @@
+//! End-to-end tests of the bridge against a fake cohost
+
+mod common;
+
+use cobridge::store::BridgedPost;
+use common::{bridged_post, FakeCohost, TestBridge};
+use http::{header, StatusCode};
+use std::sync::atomic::Ordering;
+
+#[tokio::test]
+async fn webfinger_actor_outbox() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge = TestBridge::spawn(cohost.clone());
+
+    let webfinger = bridge
+        .get(
+            "/.well-known/webfinger?resource=acct:example-artist@bridge.test",
+            "application/jrd+json",
+        )
+        .await;
+    assert_eq!(webfinger.status, StatusCode::OK);
+    assert_eq!(webfinger.body["subject"], "acct:example-artist@bridge.test");
+    let actor_url = webfinger.body["links"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|link| link["rel"] == "self")
+        .and_then(|link| link["href"].as_str())
+        .unwrap();
+    assert_eq!(actor_url, "https://bridge.test/projects/12345");
+
+    let actor = bridge.get_activity(actor_url).await;
+    assert_eq!(actor.status, StatusCode::OK);
+    assert_eq!(
+        actor.headers[header::CONTENT_TYPE],
+        "application/activity+json; charset=utf-8"
+    );
+    assert_eq!(actor.body["id"], actor_url);
+    assert_eq!(actor.body["type"], "Person");
+    assert_eq!(actor.body["preferredUsername"], "example-artist");
+    assert_eq!(actor.body["name"], "Example Artist");
+    assert_eq!(actor.body["url"], "https://cohost.org/example-artist");
+    assert_eq!(actor.body["manuallyApprovesFollowers"], false);
+    assert_eq!(
+        actor.body["publicKey"]["id"],
+        "https://bridge.test/projects/12345#main-key"
+    );
+    assert!(actor.body["publicKey"]["publicKeyPem"]
+        .as_str()
+        .unwrap()
+        .starts_with("-----BEGIN PUBLIC KEY-----"));
+    assert_eq!(actor.body["attachment"][0]["value"], "they/them");
+
+    // The key is kept, so fetching again gives the same one
+    let again = bridge.get_activity(actor_url).await;
+    assert_eq!(again.body["publicKey"], actor.body["publicKey"]);
+
+    // The outbox itself only links to its pages, without asking cohost
+    let queries = cohost.trpc_queries.load(Ordering::SeqCst);
+    let outbox = bridge
+        .get_activity(actor.body["outbox"].as_str().unwrap())
+        .await;
+    assert_eq!(outbox.status, StatusCode::OK);
+    assert_eq!(outbox.body["type"], "OrderedCollection");
+    assert_eq!(cohost.trpc_queries.load(Ordering::SeqCst), queries);
+
+    let page = bridge
+        .get_activity(outbox.body["first"].as_str().unwrap())
+        .await;
+    assert_eq!(page.status, StatusCode::OK);
+    assert_eq!(page.body["type"], "OrderedCollectionPage");
+    assert_eq!(page.body["partOf"], outbox.body["id"]);
+    assert!(page.body.get("next").is_none());
+    let items = page.body["orderedItems"].as_array().unwrap();
+    assert_eq!(items.len(), 3);
+
+    // Shares of projects which haven't agreed to be bridged only link to cohost
+    assert_eq!(items[1]["type"], "Create");
+    let share = &items[1]["object"];
+    assert!(share.get("quoteUrl").is_none());
+    assert!(share["content"]
+        .as_str()
+        .unwrap()
+        .contains(r#"<a href="https://cohost.org/other-project/post/900-a-very-good-bird""#));
+
+    let create = &items[2];
+    assert_eq!(create["type"], "Create");
+    assert_eq!(create["actor"], actor_url);
+    let note = &create["object"];
+    assert_eq!(note["type"], "Note");
+    assert_eq!(note["id"], "https://bridge.test/projects/12345/posts/1001");
+    assert_eq!(note["attributedTo"], actor_url);
+    assert_eq!(note["published"], "2022-11-19T15:45:12Z");
+    assert_eq!(note["summary"], "eye contact");
+    assert_eq!(note["sensitive"], true);
+    assert_eq!(
+        note["to"][0],
+        "https://www.w3.org/ns/activitystreams#Public"
+    );
+    let content = note["content"].as_str().unwrap();
+    assert!(content.starts_with("<p><strong>new painting</strong></p>"));
+    assert!(content.contains("a heron at <em>dusk</em><br>"));
+    assert!(!content.contains("style="));
+    assert_eq!(note["tag"][1]["name"], "#DigitalArt");
+    assert_eq!(note["attachment"][0]["type"], "Image");
+    assert_eq!(note["attachment"][0]["mediaType"], "image/png");
+    assert_eq!(
+        note["attachment"][0]["name"],
+        "a grey heron standing in shallow water"
+    );
+}
+
+#[tokio::test]
+async fn post_objects_are_dereferenceable() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+    bridge.record_post(&bridged_post(12345, "example-artist", 1001), &[]);
+
+    // Post URLs by handle redirect to the canonical ID
+    let note = bridge
+        .get_activity("https://bridge.test/users/example-artist/posts/1001")
+        .await;
+    assert_eq!(note.status, StatusCode::OK);
+    assert_eq!(note.body["type"], "Note");
+    assert_eq!(
+        note.body["id"],
+        "https://bridge.test/projects/12345/posts/1001"
+    );
+    assert_eq!(
+        note.body["url"],
+        "https://cohost.org/example-artist/post/1001-new-painting"
+    );
+
+    // A post has to be fetched through the project that wrote it
+    let wrong_project = bridge
+        .get_activity("https://bridge.test/users/private-artist/posts/1001")
+        .await;
+    assert_eq!(wrong_project.status, StatusCode::NOT_FOUND);
+
+    // Posts the outbox links to exist before the poller bridges them
+    let outbox = bridge.get_activity("/projects/12345/outbox?page=0").await;
+    let linked = outbox.body["orderedItems"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|item| item["object"]["id"] == "https://bridge.test/projects/12345/posts/998");
+    assert!(linked);
+    let unbridged = bridge.get_activity("/projects/12345/posts/998").await;
+    assert_eq!(unbridged.status, StatusCode::OK);
+    assert_eq!(
+        unbridged.body["id"],
+        "https://bridge.test/projects/12345/posts/998"
+    );
+    let missing = bridge.get_activity("/projects/12345/posts/999").await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn profile_links_only_use_safe_schemes() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge = TestBridge::spawn(cohost.clone());
+    bridge.get_activity("/users/example-artist").await;
+
+    let actor = bridge.get_activity("/projects/12345").await;
+    assert_eq!(actor.body["attachment"][1]["name"], "Link");
+    assert!(actor.body["attachment"][1]["value"]
+        .as_str()
+        .unwrap()
+        .contains(r#"href="https://example.com/""#));
+
+    for url in [
+        "javascript:alert(1)",
+        "JavaScript:alert(1)",
+        "java\tscript:alert(1)",
+        "data:text/html,hi",
+    ] {
+        cohost.edit_project("example-artist", |project| {
+            project["url"] = serde_json::json!(url)
+        });
+        let actor = bridge.get_activity("/projects/12345").await;
+        assert_eq!(actor.status, StatusCode::OK);
+        assert_eq!(
+            actor.body["attachment"].as_array().unwrap().len(),
+            1,
+            "{}",
+            url
+        );
+    }
+}
+
+#[tokio::test]
+async fn tag_pages_past_the_end_are_empty() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+    bridge.record_post(&bridged_post(12345, "example-artist", 1001), &["birds"]);
+
+    let page = bridge.get_activity("/tags/birds?page=1").await;
+    assert_eq!(page.status, StatusCode::OK);
+    assert_eq!(page.body["orderedItems"], serde_json::json!([]));
+    let page = bridge
+        .get_activity("/tags/birds?page=461168601842738790")
+        .await;
+    assert_eq!(page.status, StatusCode::OK);
+    assert_eq!(page.body["orderedItems"], serde_json::json!([]));
+
+    // Pages too far to have an offset don't exist
+    let page = bridge
+        .get_activity(&format!("/tags/birds?page={}", u64::MAX))
+        .await;
+    assert_eq!(page.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn unknown_projects_are_not_found() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+
+    let webfinger = bridge
+        .get(
+            "/.well-known/webfinger?resource=acct:nobody@bridge.test",
+            "application/jrd+json",
+        )
+        .await;
+    assert_eq!(webfinger.status, StatusCode::NOT_FOUND);
+
+    let actor = bridge.get_activity("/users/nobody").await;
+    assert_eq!(actor.status, StatusCode::NOT_FOUND);
+
+    let outbox = bridge.get_activity("/users/nobody/outbox?page=0").await;
+    assert_eq!(outbox.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn browsers_are_redirected_to_cohost() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+
+    let actor = bridge.get("/users/example-artist", "text/html").await;
+    assert_eq!(actor.status, StatusCode::FOUND);
+    assert_eq!(
+        actor.headers[header::LOCATION],
+        "https://cohost.org/example-artist"
+    );
+
+    let ld_json = bridge
+        .get(
+            "/users/example-artist",
+            r#"application/ld+json; profile="https://www.w3.org/ns/activitystreams""#,
+        )
+        .await;
+    assert_eq!(ld_json.status, StatusCode::OK);
+    assert_eq!(
+        ld_json.body["id"],
+        "https://bridge.test/users/example-artist"
+    );
+    assert_eq!(
+        ld_json.body["movedTo"],
+        "https://bridge.test/projects/12345"
+    );
+
+    // Posts are sent to their page on cohost, which has a slug
+    bridge.record_post(
+        &BridgedPost {
+            url: "https://cohost.org/example-artist/post/1001-new-painting".to_string(),
+            ..bridged_post(12345, "example-artist", 1001)
+        },
+        &[],
+    );
+    let post = bridge.get("/projects/12345/posts/1001", "text/html").await;
+    assert_eq!(post.status, StatusCode::FOUND);
+    assert_eq!(
+        post.headers[header::LOCATION],
+        "https://cohost.org/example-artist/post/1001-new-painting"
+    );
+
+    // Which is found on cohost for posts bridged before it was kept
+    bridge.record_post(&bridged_post(12345, "example-artist", 998), &[]);
+    let post = bridge
+        .get("/users/example-artist/posts/998", "text/html")
+        .await;
+    assert_eq!(post.status, StatusCode::FOUND);
+    assert_eq!(
+        post.headers[header::LOCATION],
+        "https://cohost.org/example-artist/post/998-pinned-commissions-info"
+    );
+
+    // As it is for posts which weren't bridged
+    let post = bridge.get("/projects/12345/posts/1002", "text/html").await;
+    assert_eq!(post.status, StatusCode::FOUND);
+    assert_eq!(
+        post.headers[header::LOCATION],
+        "https://cohost.org/example-artist/post/1002"
+    );
+    let post = bridge.get("/projects/12345/posts/999", "text/html").await;
+    assert_eq!(post.status, StatusCode::NOT_FOUND);
+}
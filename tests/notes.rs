@@ -0,0 +1,73 @@
+//! Golden-file tests of translating cohost posts into Notes. Each
+//! `tests/fixtures/notes/<name>.post.json` is a post as cohost returns it,
+//! and `<name>.note.json` the Note it should become. Run with
+//! `UPDATE_GOLDEN=1` to rewrite the expected Notes after an intended change
+
+use cobridge::activitypub::activitystreams::Note;
+use cobridge::cohost::types::Post;
+use serde_json::Value;
+use std::path::PathBuf;
+
+const DOMAIN: &str = "bridge.test";
+const ADULT_CONTENT_WARNING: &str = "18+";
+
+fn check_golden(name: &str) {
+    let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/notes");
+    let post: Post = serde_json::from_str(
+        &std::fs::read_to_string(directory.join(format!("{}.post.json", name))).unwrap(),
+    )
+    .unwrap();
+    let note =
+        serde_json::to_value(Note::with_post(DOMAIN, &post, ADULT_CONTENT_WARNING, None)).unwrap();
+
+    let expected_path = directory.join(format!("{}.note.json", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        let mut text = serde_json::to_string_pretty(&note).unwrap();
+        text.push('\n');
+        std::fs::write(&expected_path, text).unwrap();
+        return;
+    }
+    let expected: Value = serde_json::from_str(
+        &std::fs::read_to_string(&expected_path)
+            .unwrap_or_else(|_| panic!("missing {:?}, run with UPDATE_GOLDEN=1", expected_path)),
+    )
+    .unwrap();
+    assert_eq!(
+        note,
+        expected,
+        "{} doesn't match {:?}:\n{}",
+        name,
+        expected_path,
+        serde_json::to_string_pretty(&note).unwrap()
+    );
+}
+
+#[test]
+fn plain_post() {
+    check_golden("plain");
+}
+
+#[test]
+fn post_with_content_warnings() {
+    check_golden("content-warning");
+}
+
+#[test]
+fn adult_post() {
+    check_golden("adult");
+}
+
+#[test]
+fn post_with_tags() {
+    check_golden("tags");
+}
+
+#[test]
+fn post_with_asks() {
+    check_golden("ask");
+}
+
+#[test]
+fn post_with_attachments() {
+    check_golden("attachments");
+}
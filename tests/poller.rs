@@ -0,0 +1,602 @@
+//! Tests of the poller against a fake cohost
+
+mod common;
+
+use cobridge::activitypub::delivery::{send_due, DeliveryConfig};
+use cobridge::activitypub::followers::Follower;
+use cobridge::consent::ConsentPolicy;
+use cobridge::poller::{check_old_posts, poll_project};
+use common::{FakeCohost, MockInbox, RemoteActor, TestBridge, PAGE_SIZE};
+use http::StatusCode;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+const PROJECT_ID: u64 = 12345;
+const HANDLE: &str = "example-artist";
+
+fn delivery_config() -> DeliveryConfig {
+    DeliveryConfig {
+        timeout: Duration::from_secs(5),
+        max_age: Duration::from_secs(24 * 60 * 60),
+    }
+}
+
+/// A new post by the fixture project, copied from an existing one
+fn new_post(cohost: &FakeCohost, post_id: u64) -> Value {
+    let mut post = cohost
+        .project(HANDLE)
+        .unwrap()
+        .posts
+        .into_iter()
+        .find(|post| post["postId"] == 1001)
+        .unwrap();
+    post["postId"] = json!(post_id);
+    post["headline"] = json!("another heron");
+    post["singlePostPageUrl"] = json!(format!(
+        "https://cohost.org/example-artist/post/{}-another-heron",
+        post_id
+    ));
+    post
+}
+
+#[tokio::test]
+async fn new_and_edited_posts_are_delivered() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge = TestBridge::spawn(cohost.clone());
+    bridge.add_follower(PROJECT_ID, HANDLE, "https://remote.test/users/alice");
+
+    // The first poll only finds out where to start
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert!(bridge.take_deliveries().is_empty());
+
+    let post = new_post(&cohost, 1004);
+    cohost.edit_posts(HANDLE, |posts| posts.insert(1, post));
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    let deliveries = bridge.take_deliveries();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["type"], "Create");
+    assert_eq!(
+        deliveries[0]["object"]["id"],
+        "https://bridge.test/projects/12345/posts/1004"
+    );
+    assert!(deliveries[0]["object"].get("updated").is_none());
+
+    // Nothing changed, so nothing is sent
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert!(bridge.take_deliveries().is_empty());
+
+    cohost.edit_posts(HANDLE, |posts| {
+        posts[1]["headline"] = json!("another heron (fixed)");
+        posts[1]["tags"] = json!(["herons"]);
+    });
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    let deliveries = bridge.take_deliveries();
+    assert_eq!(deliveries.len(), 1);
+    let update = &deliveries[0];
+    assert_eq!(update["type"], "Update");
+    assert_eq!(update["actor"], "https://bridge.test/projects/12345");
+    assert_eq!(
+        update["to"],
+        json!(["https://www.w3.org/ns/activitystreams#Public"])
+    );
+    assert_eq!(
+        update["cc"],
+        json!(["https://bridge.test/projects/12345/followers"])
+    );
+    let note = &update["object"];
+    assert_eq!(note["id"], "https://bridge.test/projects/12345/posts/1004");
+    assert_eq!(note["name"], "another heron (fixed)");
+    assert_eq!(note["tag"][0]["name"], "#herons");
+    assert!(note["updated"].is_string());
+    assert_ne!(note["updated"], note["published"]);
+
+    // The edit is only sent once
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert!(bridge.take_deliveries().is_empty());
+
+    // Posts from before the bridge started following aren't updated
+    cohost.edit_posts(HANDLE, |posts| posts[2]["headline"] = json!("edited"));
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert!(bridge.take_deliveries().is_empty());
+}
+
+/// Bridge a new post 1004 and poll until it's been sent
+async fn bridge_new_post(cohost: &FakeCohost, bridge: &TestBridge) {
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    let post = new_post(cohost, 1004);
+    cohost.edit_posts(HANDLE, |posts| posts.insert(1, post));
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert_eq!(bridge.take_deliveries().len(), 1);
+}
+
+#[tokio::test]
+async fn deleted_posts_are_deleted_for_followers() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge = TestBridge::spawn(cohost.clone());
+    bridge.add_follower(PROJECT_ID, HANDLE, "https://remote.test/users/alice");
+    bridge_new_post(&cohost, &bridge).await;
+
+    // It has to be missing for more than one poll
+    cohost.edit_posts(HANDLE, |posts| {
+        posts.remove(1);
+    });
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert!(bridge.take_deliveries().is_empty());
+
+    // Fetching it doesn't decide it's deleted either
+    let object = bridge
+        .get_activity("/users/example-artist/posts/1004")
+        .await;
+    assert_eq!(object.status, StatusCode::NOT_FOUND);
+    assert!(bridge.take_deliveries().is_empty());
+
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    let deliveries = bridge.take_deliveries();
+    assert_eq!(deliveries.len(), 1);
+    let delete = &deliveries[0];
+    assert_eq!(delete["type"], "Delete");
+    assert_eq!(delete["actor"], "https://bridge.test/projects/12345");
+    assert_eq!(
+        delete["to"],
+        json!(["https://www.w3.org/ns/activitystreams#Public"])
+    );
+    assert_eq!(
+        delete["cc"],
+        json!(["https://bridge.test/projects/12345/followers"])
+    );
+    assert_eq!(delete["object"]["type"], "Tombstone");
+    assert_eq!(
+        delete["object"]["id"],
+        "https://bridge.test/projects/12345/posts/1004"
+    );
+    assert!(delete["object"]["deleted"].is_string());
+
+    // It stays gone, and isn't deleted twice
+    let object = bridge
+        .get_activity("/users/example-artist/posts/1004")
+        .await;
+    assert_eq!(object.status, StatusCode::GONE);
+    assert_eq!(object.body["deleted"], delete["object"]["deleted"]);
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert!(bridge.take_deliveries().is_empty());
+}
+
+#[tokio::test]
+async fn old_deleted_posts_are_deleted_for_followers() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge = TestBridge::spawn(cohost.clone());
+    bridge.add_follower(PROJECT_ID, HANDLE, "https://remote.test/users/alice");
+    bridge_new_post(&cohost, &bridge).await;
+
+    // Enough newer posts that it is no longer on the first page
+    for post_id in 1005..1005 + PAGE_SIZE as u64 {
+        let post = new_post(&cohost, post_id);
+        cohost.edit_posts(HANDLE, |posts| posts.insert(1, post));
+    }
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert_eq!(bridge.take_deliveries().len(), PAGE_SIZE);
+
+    cohost.edit_posts(HANDLE, |posts| posts.retain(|post| post["postId"] != 1004));
+    for _ in 0..2 {
+        poll_project(&bridge.state, PROJECT_ID, HANDLE)
+            .await
+            .unwrap();
+    }
+    assert!(bridge.take_deliveries().is_empty());
+
+    // Checking older posts finds it missing, and the next poll deletes it
+    check_old_posts(&bridge.state, PROJECT_ID).await.unwrap();
+    assert!(bridge.take_deliveries().is_empty());
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    let deliveries = bridge.take_deliveries();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["type"], "Delete");
+    assert_eq!(
+        deliveries[0]["object"]["id"],
+        "https://bridge.test/projects/12345/posts/1004"
+    );
+    let object = bridge
+        .get_activity("/users/example-artist/posts/1004")
+        .await;
+    assert_eq!(object.status, StatusCode::GONE);
+
+    // Posts which still exist are left alone
+    check_old_posts(&bridge.state, PROJECT_ID).await.unwrap();
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert!(bridge.take_deliveries().is_empty());
+}
+
+#[tokio::test]
+async fn posts_cohost_fails_to_load_are_not_deleted() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge = TestBridge::spawn(cohost.clone());
+    bridge.add_follower(PROJECT_ID, HANDLE, "https://remote.test/users/alice");
+    bridge_new_post(&cohost, &bridge).await;
+
+    let mut post = Value::Null;
+    cohost.edit_posts(HANDLE, |posts| post = posts.remove(1));
+    cohost.set_pages_failing(true);
+    for _ in 0..3 {
+        poll_project(&bridge.state, PROJECT_ID, HANDLE)
+            .await
+            .unwrap();
+    }
+    assert!(bridge.take_deliveries().is_empty());
+    let bridged = bridge.state.store.bridged_post(1004).unwrap().unwrap();
+    assert_eq!(bridged.deleted, None);
+    assert_eq!(bridged.missing, None);
+
+    // Going missing once and coming back starts over
+    cohost.set_pages_failing(false);
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert!(bridge
+        .state
+        .store
+        .bridged_post(1004)
+        .unwrap()
+        .unwrap()
+        .missing
+        .is_some());
+    cohost.edit_posts(HANDLE, |posts| posts.insert(1, post));
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert_eq!(
+        bridge
+            .state
+            .store
+            .bridged_post(1004)
+            .unwrap()
+            .unwrap()
+            .missing,
+        None
+    );
+    cohost.edit_posts(HANDLE, |posts| {
+        posts.remove(1);
+    });
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert!(bridge.take_deliveries().is_empty());
+}
+
+#[tokio::test]
+async fn renamed_projects_keep_their_followers() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge = TestBridge::spawn(cohost.clone());
+    bridge.add_follower(PROJECT_ID, HANDLE, "https://remote.test/users/alice");
+
+    // Renames are found through a bridged post, so bridge one first
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    let post = new_post(&cohost, 1004);
+    cohost.edit_posts(HANDLE, |posts| posts.insert(1, post));
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    bridge.take_deliveries();
+
+    cohost.rename_project(HANDLE, "renamed-artist");
+
+    // Visitors finding the project by its new handle change nothing, only
+    // the poller records renames and tells followers
+    let actor = bridge.get_activity("/users/renamed-artist").await;
+    assert_eq!(actor.status, StatusCode::OK);
+    let webfinger = bridge
+        .get(
+            "/.well-known/webfinger?resource=acct:renamed-artist@bridge.test",
+            "application/jrd+json",
+        )
+        .await;
+    assert_eq!(webfinger.status, StatusCode::OK);
+    assert!(bridge.take_deliveries().is_empty());
+    assert_eq!(
+        bridge.state.store.project_handle(PROJECT_ID).unwrap(),
+        Some(HANDLE.to_string())
+    );
+
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    let deliveries = bridge.take_deliveries();
+    assert_eq!(deliveries.len(), 1);
+    let update = &deliveries[0];
+    assert_eq!(update["type"], "Update");
+    assert_eq!(update["actor"], "https://bridge.test/projects/12345");
+    assert_eq!(update["object"]["id"], "https://bridge.test/projects/12345");
+    assert_eq!(update["object"]["preferredUsername"], "renamed-artist");
+    assert_eq!(
+        update["object"]["alsoKnownAs"],
+        json!([
+            "https://bridge.test/users/renamed-artist",
+            "https://bridge.test/users/example-artist",
+        ])
+    );
+    assert_eq!(
+        bridge.state.store.followed_projects().unwrap(),
+        vec![(PROJECT_ID, "renamed-artist".to_string())]
+    );
+
+    let actor = bridge.get_activity("/projects/12345").await;
+    assert_eq!(actor.status, StatusCode::OK);
+    assert_eq!(actor.body["preferredUsername"], "renamed-artist");
+    let alias = bridge
+        .get("/users/renamed-artist/outbox", "application/activity+json")
+        .await;
+    assert_eq!(alias.status, StatusCode::FOUND);
+    assert_eq!(
+        alias.headers[http::header::LOCATION],
+        "https://bridge.test/projects/12345/outbox"
+    );
+    // Links to the old handle still lead to the project
+    let old_alias = bridge
+        .get(
+            "/users/example-artist/posts/1004",
+            "application/activity+json",
+        )
+        .await;
+    assert_eq!(old_alias.status, StatusCode::FOUND);
+    assert_eq!(
+        old_alias.headers[http::header::LOCATION],
+        "https://bridge.test/projects/12345/posts/1004"
+    );
+
+    poll_project(&bridge.state, PROJECT_ID, "renamed-artist")
+        .await
+        .unwrap();
+    assert!(bridge.take_deliveries().is_empty());
+
+    // Until another project takes the handle
+    cohost.rename_project("other-project", HANDLE);
+    let old_alias = bridge.get_activity("/users/example-artist").await;
+    assert_eq!(old_alias.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn renames_are_found_before_anything_is_bridged() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge = TestBridge::spawn(cohost.clone());
+    bridge.add_follower(PROJECT_ID, HANDLE, "https://remote.test/users/alice");
+
+    // The first poll only notes the newest post
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert!(bridge.take_deliveries().is_empty());
+    assert!(bridge
+        .state
+        .store
+        .bridged_posts(PROJECT_ID, 0)
+        .unwrap()
+        .is_empty());
+
+    cohost.rename_project(HANDLE, "renamed-artist");
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    let deliveries = bridge.take_deliveries();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["type"], "Update");
+    assert_eq!(
+        deliveries[0]["object"]["preferredUsername"],
+        "renamed-artist"
+    );
+    let actor = bridge.get_activity("/projects/12345").await;
+    assert_eq!(actor.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn projects_taking_a_former_handle_are_not_bridged() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge = TestBridge::spawn(cohost.clone());
+    bridge.add_follower(PROJECT_ID, HANDLE, "https://remote.test/users/alice");
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+
+    // The other project has posted since the last poll
+    cohost.edit_posts("other-project", |posts| posts[0]["postId"] = json!(1100));
+    cohost.rename_project(HANDLE, "renamed-artist");
+    cohost.rename_project("other-project", HANDLE);
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    let deliveries = bridge.take_deliveries();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["type"], "Update");
+    assert_eq!(
+        deliveries[0]["object"]["preferredUsername"],
+        "renamed-artist"
+    );
+    assert_eq!(
+        bridge.state.store.followed_projects().unwrap(),
+        vec![(PROJECT_ID, "renamed-artist".to_string())]
+    );
+    assert!(bridge.state.store.bridged_post(1100).unwrap().is_none());
+}
+
+#[tokio::test]
+async fn followers_of_handle_based_ids_are_moved() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+    let alice = RemoteActor::spawn("alice");
+    let inbox = MockInbox::spawn();
+    bridge
+        .state
+        .store
+        .set_project_handle(PROJECT_ID, HANDLE)
+        .unwrap();
+    bridge
+        .state
+        .store
+        .add_follower(
+            PROJECT_ID,
+            &Follower {
+                actor: alice.id.clone(),
+                inbox: inbox.url("/users/alice/inbox"),
+                shared_inbox: None,
+                legacy_handle: Some(HANDLE.to_string()),
+            },
+        )
+        .unwrap();
+
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    send_due(&bridge.state, &delivery_config()).await;
+    let received = inbox.take_received();
+    assert_eq!(received.len(), 1);
+    let move_activity = &received[0].activity();
+    assert_eq!(move_activity["type"], "Move");
+    assert_eq!(
+        move_activity["actor"],
+        "https://bridge.test/users/example-artist"
+    );
+    assert_eq!(move_activity["object"], move_activity["actor"]);
+    assert_eq!(
+        move_activity["target"],
+        "https://bridge.test/projects/12345"
+    );
+    let actor = bridge.get_activity("/projects/12345").await;
+    assert_eq!(
+        actor.body["alsoKnownAs"],
+        json!(["https://bridge.test/users/example-artist"])
+    );
+
+    // It's signed by the old actor, which still has its own key
+    let key = bridge.verify_sent(&received[0]).await.unwrap();
+    assert_eq!(key.id, "https://bridge.test/users/example-artist#main-key");
+    assert_eq!(key.owner, "https://bridge.test/users/example-artist");
+    let legacy = bridge.get_activity("/users/example-artist").await;
+    assert_eq!(
+        legacy.body["id"],
+        "https://bridge.test/users/example-artist"
+    );
+    assert_eq!(legacy.body["movedTo"], "https://bridge.test/projects/12345");
+
+    // Their server follows the new ID, then undoes the old follow
+    let follow = |name: &str, object: &str| {
+        json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": format!("{}#follows/{}", alice.id, name),
+            "type": "Follow",
+            "actor": alice.id,
+            "object": object,
+        })
+    };
+    let response = bridge
+        .post_signed(
+            "/inbox",
+            &alice,
+            &follow("new", "https://bridge.test/projects/12345"),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+    let old_follow = follow("old", "https://bridge.test/users/example-artist");
+    let undo = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}#undo", old_follow["id"].as_str().unwrap()),
+        "type": "Undo",
+        "actor": alice.id,
+        "object": old_follow,
+    });
+    let response = bridge.post_signed("/inbox", &alice, &undo).await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+
+    let followers = bridge.state.store.followers(PROJECT_ID).unwrap();
+    assert_eq!(followers.len(), 1);
+    assert_eq!(followers[0].legacy_handle, None);
+
+    // Nobody is left to move
+    bridge.take_deliveries();
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    assert!(bridge.take_deliveries().is_empty());
+}
+
+#[tokio::test]
+async fn shares_of_bridged_projects_are_announced() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge =
+        TestBridge::spawn_with_consent(cohost.clone(), ConsentPolicy::new("#cobridge", &[67890]));
+    bridge.add_follower(PROJECT_ID, HANDLE, "https://remote.test/users/alice");
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+
+    // Shares which were never bridged still only link to cohost
+    let outbox = bridge.get_activity("/projects/12345/outbox?page=0").await;
+    assert_eq!(outbox.body["orderedItems"][1]["type"], "Create");
+
+    let mut share = cohost
+        .project(HANDLE)
+        .unwrap()
+        .posts
+        .into_iter()
+        .find(|post| post["postId"] == 1002)
+        .unwrap();
+    share["postId"] = json!(1005);
+    share["singlePostPageUrl"] = json!("https://cohost.org/example-artist/post/1005-");
+    cohost.edit_posts(HANDLE, |posts| posts.insert(1, share));
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+
+    let deliveries = bridge.take_deliveries();
+    assert_eq!(deliveries.len(), 1);
+    let announce = &deliveries[0];
+    assert_eq!(announce["type"], "Announce");
+    assert_eq!(
+        announce["object"],
+        "https://bridge.test/projects/67890/posts/900"
+    );
+    assert_eq!(announce["cc"][1], "https://bridge.test/projects/67890");
+
+    // The shared post and its project can be fetched by their IDs
+    let note = bridge.get_activity("/projects/67890/posts/900").await;
+    assert_eq!(note.status, StatusCode::OK);
+    assert_eq!(
+        note.body["attributedTo"],
+        "https://bridge.test/projects/67890"
+    );
+    let actor = bridge.get_activity("/projects/67890").await;
+    assert_eq!(actor.status, StatusCode::OK);
+    assert_eq!(actor.body["preferredUsername"], "other-project");
+
+    // Now that it's bridged, the older share is announced too
+    let outbox = bridge.get_activity("/projects/12345/outbox?page=0").await;
+    let items = outbox.body["orderedItems"].as_array().unwrap();
+    assert_eq!(items[1]["type"], "Announce");
+    assert_eq!(items[2]["type"], "Announce");
+}
@@ -0,0 +1,251 @@
+//! Tests of how private projects are bridged
+
+mod common;
+
+use cobridge::consent::ConsentPolicy;
+use cobridge::poller::poll_project;
+use cobridge::store::BridgedPost;
+use common::{bridged_post, FakeCohost, RemoteActor, TestBridge};
+use http::StatusCode;
+use serde_json::{json, Value};
+
+const PROJECT_ID: u64 = 24680;
+const HANDLE: &str = "private-artist";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+fn addressed_publicly(object: &Value) -> bool {
+    [&object["to"], &object["cc"]]
+        .iter()
+        .filter_map(|audience| audience.as_array())
+        .flatten()
+        .any(|audience| audience == PUBLIC)
+}
+
+/// A post by the private project as the poller records it
+fn private_post(post_id: u64) -> BridgedPost {
+    BridgedPost {
+        private: true,
+        ..bridged_post(PROJECT_ID, HANDLE, post_id)
+    }
+}
+
+/// Items of a collection, which are left out when there are none
+fn items(collection: &Value) -> Vec<Value> {
+    collection["orderedItems"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+}
+
+#[tokio::test]
+async fn private_posts_are_only_shown_to_followers() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+    bridge.record_post(&private_post(2001), &[]);
+
+    let actor = bridge.get_activity("/users/private-artist").await;
+    assert_eq!(actor.status, StatusCode::OK);
+    assert_eq!(actor.body["manuallyApprovesFollowers"], true);
+
+    let post = bridge
+        .get_activity("/users/private-artist/posts/2001")
+        .await;
+    assert_eq!(post.status, StatusCode::NOT_FOUND);
+    let outbox = bridge
+        .get_activity("/users/private-artist/outbox?page=0")
+        .await;
+    assert_eq!(outbox.status, StatusCode::OK);
+    assert!(items(&outbox.body).is_empty());
+    let featured = bridge.get_activity("/users/private-artist/featured").await;
+    assert!(items(&featured.body).is_empty());
+
+    // Signing isn't enough on its own
+    let stranger = RemoteActor::spawn("stranger");
+    let post = bridge
+        .get_signed("/users/private-artist/posts/2001", &stranger)
+        .await;
+    assert_eq!(post.status, StatusCode::NOT_FOUND);
+
+    let follower = RemoteActor::spawn("alice");
+    bridge.add_follower(PROJECT_ID, HANDLE, &follower.id);
+    let post = bridge
+        .get_signed("/users/private-artist/posts/2001", &follower)
+        .await;
+    assert_eq!(post.status, StatusCode::OK);
+    assert_eq!(
+        post.body["to"],
+        json!(["https://bridge.test/projects/24680/followers"])
+    );
+    assert!(!addressed_publicly(&post.body));
+
+    let outbox = bridge
+        .get_signed("/users/private-artist/outbox?page=0", &follower)
+        .await;
+    let items = items(&outbox.body);
+    assert_eq!(items.len(), 1);
+    assert!(!addressed_publicly(&items[0]));
+    assert!(!addressed_publicly(&items[0]["object"]));
+}
+
+#[tokio::test]
+async fn only_followers_and_allowed_instance_actors_see_private_posts() {
+    let mut actors = RemoteActor::spawn_on_one_server(&["alice", "bob", "instance"]);
+    let (alice, bob, instance) = (actors.remove(0), actors.remove(0), actors.remove(0));
+    let elsewhere = RemoteActor::spawn("instance");
+    let bridge = TestBridge::spawn_with(
+        FakeCohost::from_fixtures(),
+        ConsentPolicy::new("#cobridge", &[]),
+        vec![instance.id.clone(), elsewhere.id.clone()],
+    );
+    bridge.add_follower(PROJECT_ID, HANDLE, &alice.id);
+    bridge.record_post(&private_post(2001), &[]);
+
+    let read = |actor| bridge.get_signed("/projects/24680/posts/2001", actor);
+    assert_eq!(read(&alice).await.status, StatusCode::OK);
+    assert_eq!(read(&instance).await.status, StatusCode::OK);
+
+    // Being on a follower's server isn't enough for anyone else
+    assert_eq!(read(&bob).await.status, StatusCode::NOT_FOUND);
+    // Nor is being an allowed instance actor of a server with no followers
+    assert_eq!(read(&elsewhere).await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn tags_only_list_posts_which_may_be_read() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+    let posts = [
+        (12345, "example-artist", 1001),
+        (PROJECT_ID, HANDLE, 2001),
+        (13579, "unbridged-artist", 3001),
+    ];
+    for (project_id, handle, post_id) in posts {
+        bridge
+            .state
+            .store
+            .set_project_handle(project_id, handle)
+            .unwrap();
+        bridge.record_post(
+            &BridgedPost {
+                private: project_id == PROJECT_ID,
+                ..bridged_post(project_id, handle, post_id)
+            },
+            &["birds"],
+        );
+    }
+
+    let page = bridge.get_activity("/tags/birds?page=0").await;
+    assert_eq!(page.status, StatusCode::OK);
+    assert_eq!(
+        items(&page.body),
+        vec![json!("https://bridge.test/projects/12345/posts/1001")]
+    );
+
+    let follower = RemoteActor::spawn("alice");
+    bridge.add_follower(PROJECT_ID, HANDLE, &follower.id);
+    let page = bridge.get_signed("/tags/birds?page=0", &follower).await;
+    let mut listed = items(&page.body);
+    listed.sort_by_key(|item| item.to_string());
+    assert_eq!(
+        listed,
+        vec![
+            json!("https://bridge.test/projects/12345/posts/1001"),
+            json!("https://bridge.test/projects/24680/posts/2001"),
+        ]
+    );
+}
+
+fn follow(actor: &RemoteActor, project_id: u64) -> Value {
+    json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}#follows/{}", actor.id, project_id),
+        "type": "Follow",
+        "actor": actor.id,
+        "object": format!("https://bridge.test/projects/{}", project_id),
+    })
+}
+
+fn undo(actor: &RemoteActor, object: Value) -> Value {
+    json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}#undo", object["id"].as_str().unwrap()),
+        "type": "Undo",
+        "actor": actor.id,
+        "object": object,
+    })
+}
+
+#[tokio::test]
+async fn follows_of_private_projects_stay_pending() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+    let alice = RemoteActor::spawn("alice");
+    // Servers find actors by handle before following them
+    bridge.get_activity("/users/example-artist").await;
+    bridge.get_activity("/users/private-artist").await;
+
+    // Public projects accept straight away
+    let response = bridge
+        .post_signed("/projects/12345/inbox", &alice, &follow(&alice, 12345))
+        .await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+    assert_eq!(bridge.state.store.followers(12345).unwrap().len(), 1);
+    let deliveries = bridge.take_deliveries();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["type"], "Accept");
+
+    let request = follow(&alice, PROJECT_ID);
+    let response = bridge.post_signed("/inbox", &alice, &request).await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+    assert!(bridge.state.store.followers(PROJECT_ID).unwrap().is_empty());
+    assert!(bridge.take_deliveries().is_empty());
+    let requests = bridge.state.store.follow_requests(PROJECT_ID).unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].follower.actor, alice.id);
+
+    let response = bridge
+        .post_signed("/inbox", &alice, &undo(&alice, request))
+        .await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+    assert!(bridge
+        .state
+        .store
+        .follow_requests(PROJECT_ID)
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn deletes_of_private_posts_stay_private() {
+    let cohost = FakeCohost::from_fixtures();
+    let bridge = TestBridge::spawn(cohost.clone());
+    bridge.add_follower(PROJECT_ID, HANDLE, "https://remote.test/users/alice");
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+
+    let mut post = cohost.project(HANDLE).unwrap().posts[0].clone();
+    post["postId"] = json!(2002);
+    cohost.edit_posts(HANDLE, |posts| posts.insert(0, post));
+    poll_project(&bridge.state, PROJECT_ID, HANDLE)
+        .await
+        .unwrap();
+    let deliveries = bridge.take_deliveries();
+    assert_eq!(deliveries.len(), 1);
+    assert!(!addressed_publicly(&deliveries[0]));
+
+    cohost.edit_posts(HANDLE, |posts| {
+        posts.remove(0);
+    });
+    for _ in 0..2 {
+        poll_project(&bridge.state, PROJECT_ID, HANDLE)
+            .await
+            .unwrap();
+    }
+    let deliveries = bridge.take_deliveries();
+    assert_eq!(deliveries.len(), 1);
+    let delete = &deliveries[0];
+    assert_eq!(delete["type"], "Delete");
+    assert_eq!(
+        delete["to"],
+        json!(["https://bridge.test/projects/24680/followers"])
+    );
+    assert!(!addressed_publicly(delete));
+}
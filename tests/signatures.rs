@@ -0,0 +1,201 @@
+//! Tests of HTTP Signatures on requests from other servers
+
+mod common;
+
+use cobridge::activitypub::{client::FederationClient, signature::digest_header};
+use common::{FakeCohost, RemoteActor, Response, TestBridge};
+use http::{header, HeaderMap, Method, Request, StatusCode};
+use hyper::Body;
+use serde_json::{json, Value};
+
+/// An activity the bridge accepts and ignores, so only the signature matters
+fn like(actor: &RemoteActor) -> Value {
+    json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}/likes/1", actor.id),
+        "type": "Like",
+        "actor": actor.id,
+        "object": "https://bridge.test/projects/12345/posts/1001",
+    })
+}
+
+/// Post `body` to the shared inbox with the given headers
+async fn post_inbox(bridge: &TestBridge, body: &str, headers: HeaderMap) -> Response {
+    let mut request = Request::post(bridge.local_uri("/inbox"))
+        .header(header::CONTENT_TYPE, "application/activity+json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    request.headers_mut().extend(headers);
+    bridge.send(request).await
+}
+
+#[tokio::test]
+async fn signed_activities_are_accepted() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+    let alice = RemoteActor::spawn("alice");
+
+    let response = bridge.post_signed("/inbox", &alice, &like(&alice)).await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+    let response = bridge
+        .post_signed("/projects/12345/inbox", &alice, &like(&alice))
+        .await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+}
+
+#[tokio::test]
+async fn unsigned_activities_are_rejected() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+    let alice = RemoteActor::spawn("alice");
+
+    let response = post_inbox(&bridge, &like(&alice).to_string(), HeaderMap::new()).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    let mut headers = HeaderMap::new();
+    headers.insert("signature", "not a signature".parse().unwrap());
+    let response = post_inbox(&bridge, &like(&alice).to_string(), headers).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn tampered_bodies_are_rejected() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+    let alice = RemoteActor::spawn("alice");
+    let uri = bridge.local_uri("/inbox");
+
+    let signed = like(&alice).to_string();
+    let mut tampered = like(&alice);
+    tampered["object"] = json!("https://bridge.test/projects/12345/posts/998");
+    let headers = alice.sign(&Method::POST, &uri, Some(signed.as_bytes()));
+    let response = post_inbox(&bridge, &tampered.to_string(), headers).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    // Changing the digest to match breaks the signature instead
+    let mut headers = alice.sign(&Method::POST, &uri, Some(signed.as_bytes()));
+    headers.insert(
+        "digest",
+        digest_header(tampered.to_string().as_bytes())
+            .parse()
+            .unwrap(),
+    );
+    let response = post_inbox(&bridge, &tampered.to_string(), headers).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    // A digest with an algorithm we can't check is malformed
+    let mut headers = alice.sign(&Method::POST, &uri, Some(signed.as_bytes()));
+    headers.insert("digest", "MD5=AAAA".parse().unwrap());
+    let response = post_inbox(&bridge, &signed, headers).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn requests_outside_the_clock_skew_are_rejected() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+    let alice = RemoteActor::spawn("alice");
+    let uri = bridge.local_uri("/inbox");
+    let body = like(&alice).to_string();
+
+    for skew in [chrono::Duration::hours(-2), chrono::Duration::hours(2)] {
+        let headers = alice.sign_at(
+            &Method::POST,
+            &uri,
+            Some(body.as_bytes()),
+            chrono::Utc::now() + skew,
+        );
+        let response = post_inbox(&bridge, &body, headers).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+
+    // Within the allowed skew is fine
+    let headers = alice.sign_at(
+        &Method::POST,
+        &uri,
+        Some(body.as_bytes()),
+        chrono::Utc::now() - chrono::Duration::minutes(30),
+    );
+    let response = post_inbox(&bridge, &body, headers).await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+}
+
+#[tokio::test]
+async fn signatures_by_the_wrong_key_are_rejected() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+    let alice = RemoteActor::spawn("alice");
+    let mallory = RemoteActor::spawn("mallory");
+
+    // Mallory signs with their own key, claiming it is Alice's
+    let impostor = mallory.claiming_key(&alice.key_id);
+    let response = bridge.post_signed("/inbox", &impostor, &like(&alice)).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    // A key which Alice's actor doesn't have
+    let impostor = alice.claiming_key(&format!("{}#other-key", alice.id));
+    let response = bridge.post_signed("/inbox", &impostor, &like(&alice)).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    // Mallory's valid signature doesn't let them speak for Alice
+    let response = bridge.post_signed("/inbox", &mallory, &like(&alice)).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn keys_must_be_owned_by_the_actor_serving_them() {
+    let bridge = TestBridge::spawn(FakeCohost::from_fixtures());
+    let alice = RemoteActor::spawn("alice");
+    let follow = |actor: &str| {
+        json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": "https://evil.test/follows/1",
+            "type": "Follow",
+            "actor": actor,
+            "object": "https://bridge.test/projects/12345",
+        })
+    };
+
+    // Mallory's key claims to be owned by Alice
+    let alice_id = alice.id.clone();
+    let mallory = RemoteActor::spawn_with_document("mallory", move |document| {
+        document["publicKey"]["owner"] = json!(alice_id);
+    });
+    let response = bridge
+        .post_signed("/inbox", &mallory, &follow(&alice.id))
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    // Or Mallory's whole document claims to be Alice
+    let alice_id = alice.id.clone();
+    let mallory = RemoteActor::spawn_with_document("mallory", move |document| {
+        document["id"] = json!(alice_id);
+        document["publicKey"]["owner"] = json!(alice_id);
+    });
+    let response = bridge
+        .post_signed("/inbox", &mallory, &follow(&alice.id))
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert!(bridge.state.store.followers(12345).unwrap().is_empty());
+
+    // As is a key served on its own, which nothing ties to its owner
+    let mallory = RemoteActor::spawn_with_document("mallory", |document| {
+        let mut key = document["publicKey"].take();
+        key["id"] = document["id"].take();
+        *document = key;
+    });
+    let impostor = mallory.claiming_key(&mallory.id);
+    let response = bridge
+        .post_signed("/inbox", &impostor, &follow(&mallory.id))
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn plain_http_is_only_used_on_loopback() {
+    let client = FederationClient::new("bridge.test");
+    let alice = RemoteActor::spawn("alice");
+    assert_eq!(client.fetch_json(&alice.id).await.unwrap()["id"], alice.id);
+
+    let err = client
+        .fetch_json("http://192.0.2.1/users/alice")
+        .await
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("plain HTTP"), "{:#}", err);
+    assert!(client.fetch_json("ftp://remote.test/alice").await.is_err());
+}
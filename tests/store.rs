@@ -0,0 +1,462 @@
+//! Tests which every store backend has to pass, and of migrating the
+//! SQLite store from older versions
+
+use chrono::{DateTime, Utc};
+use cobridge::activitypub::{
+    delivery::Delivery,
+    followers::{FollowRequest, Follower},
+};
+use cobridge::store::{BridgedPost, CachedActor, MemoryStore, SqliteStore, Store};
+use rusqlite::{params, Connection};
+use std::path::{Path, PathBuf};
+
+/// A directory under the system temporary directory, removed when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "cobridge-{}-{}-{:016x}",
+            name,
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    fn write(&self, name: &str, contents: &str) {
+        let path = self.0.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn time(text: &str) -> DateTime<Utc> {
+    text.parse().unwrap()
+}
+
+fn follower(actor: &str, inbox: &str) -> Follower {
+    Follower {
+        actor: actor.to_string(),
+        inbox: inbox.to_string(),
+        shared_inbox: Some("https://remote.test/inbox".to_string()),
+        legacy_handle: None,
+    }
+}
+
+fn bridged_post(post_id: u64, published: &str) -> BridgedPost {
+    BridgedPost {
+        post_id,
+        project_id: 12345,
+        handle: "example-artist".to_string(),
+        published: time(published),
+        content_hash: format!("hash-{}", post_id),
+        deleted: None,
+        url: format!("https://cohost.org/example-artist/post/{}-slug", post_id),
+        missing: None,
+        private: false,
+    }
+}
+
+fn delivery(id: &str, attempts: u32) -> Delivery {
+    Delivery {
+        id: id.to_string(),
+        project_id: 12345,
+        actor: "https://bridge.test/projects/12345".to_string(),
+        inbox: "https://remote.test/inbox".to_string(),
+        activity: r#"{"type":"Create"}"#.to_string(),
+        created: time("2022-11-01T12:00:00Z"),
+        attempts,
+        next_attempt: time("2022-11-01T12:05:00Z"),
+    }
+}
+
+fn post_ids(posts: &[BridgedPost]) -> Vec<u64> {
+    posts.iter().map(|post| post.post_id).collect()
+}
+
+/// Behaviour the rest of the bridge relies on from any [Store]
+fn check_contract(store: &dyn Store) {
+    // Keys
+    assert_eq!(store.key(12345).unwrap(), None);
+    store.set_key(12345, "first key").unwrap();
+    store.set_key(12345, "second key").unwrap();
+    store.set_key(24680, "other key").unwrap();
+    assert_eq!(store.key(12345).unwrap().as_deref(), Some("second key"));
+    assert_eq!(store.count_projects().unwrap(), 2);
+
+    // Handles
+    assert_eq!(store.project_handle(12345).unwrap(), None);
+    assert_eq!(store.set_project_handle(12345, "old-artist").unwrap(), None);
+    assert_eq!(store.set_project_handle(12345, "old-artist").unwrap(), None);
+    assert!(store.former_handles(12345).unwrap().is_empty());
+    assert_eq!(
+        store
+            .set_project_handle(12345, "example-artist")
+            .unwrap()
+            .as_deref(),
+        Some("old-artist")
+    );
+    assert_eq!(
+        store.project_handle(12345).unwrap().as_deref(),
+        Some("example-artist")
+    );
+    assert_eq!(store.former_handles(12345).unwrap(), vec!["old-artist"]);
+    assert_eq!(
+        store.project_with_former_handle("old-artist").unwrap(),
+        Some(12345)
+    );
+    assert_eq!(
+        store.project_with_former_handle("example-artist").unwrap(),
+        None
+    );
+
+    // Followers
+    let alice = follower("https://remote.test/users/alice", "https://remote.test/a");
+    let bob = follower("https://remote.test/users/bob", "https://remote.test/b");
+    store.add_follower(12345, &alice).unwrap();
+    store.add_follower(12345, &bob).unwrap();
+    let moved_bob = Follower {
+        inbox: "https://remote.test/bob/inbox".to_string(),
+        legacy_handle: Some("old-artist".to_string()),
+        ..bob.clone()
+    };
+    store.add_follower(12345, &moved_bob).unwrap();
+    let mut followers = store.followers(12345).unwrap();
+    followers.sort_by(|a, b| a.actor.cmp(&b.actor));
+    assert_eq!(followers, vec![alice.clone(), moved_bob]);
+    assert_eq!(
+        store.followed_projects().unwrap(),
+        vec![(12345, "example-artist".to_string())]
+    );
+    assert!(store.remove_follower(12345, &bob.actor).unwrap());
+    assert!(!store.remove_follower(12345, &bob.actor).unwrap());
+    assert_eq!(store.followers(12345).unwrap(), vec![alice.clone()]);
+    assert!(store.followers(24680).unwrap().is_empty());
+
+    // Follow requests
+    let request = FollowRequest {
+        follower: bob.clone(),
+        activity: r#"{"type":"Follow"}"#.to_string(),
+        received: time("2022-11-01T12:00:00Z"),
+    };
+    store.add_follow_request(24680, &request).unwrap();
+    let requests = store.follow_requests(24680).unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].follower, bob);
+    assert_eq!(requests[0].activity, request.activity);
+    assert_eq!(requests[0].received, request.received);
+    // Pending follows don't make a project followed
+    assert_eq!(store.followed_projects().unwrap().len(), 1);
+    assert!(store.remove_follow_request(24680, &bob.actor).unwrap());
+    assert!(!store.remove_follow_request(24680, &bob.actor).unwrap());
+    assert!(store.follow_requests(24680).unwrap().is_empty());
+
+    // Seen posts
+    assert_eq!(store.last_seen_post(12345).unwrap(), None);
+    store.set_last_seen_post(12345, 1001).unwrap();
+    store.set_last_seen_post(12345, 1002).unwrap();
+    assert_eq!(store.last_seen_post(12345).unwrap(), Some(1002));
+
+    // Bridged posts and hashtags
+    store
+        .record_post(
+            &bridged_post(1001, "2022-11-01T12:00:00Z"),
+            &["birds".into()],
+        )
+        .unwrap();
+    store
+        .record_post(
+            &bridged_post(1002, "2022-11-02T12:00:00Z"),
+            &["birds".into(), "art".into()],
+        )
+        .unwrap();
+    store
+        .record_post(&bridged_post(1003, "2022-11-03T12:00:00Z"), &[])
+        .unwrap();
+    let edited = BridgedPost {
+        content_hash: "edited".to_string(),
+        private: true,
+        ..bridged_post(1003, "2022-11-03T12:00:00Z")
+    };
+    store.record_post(&edited, &["Art".into()]).unwrap();
+    assert_eq!(
+        store.bridged_post(1003).unwrap().unwrap().content_hash,
+        "edited"
+    );
+    assert!(store.bridged_post(1003).unwrap().unwrap().private);
+    assert!(!store.bridged_post(1001).unwrap().unwrap().private);
+    assert_eq!(
+        store.bridged_post(1003).unwrap().unwrap().url,
+        "https://cohost.org/example-artist/post/1003-slug"
+    );
+    assert!(store.bridged_post(999).unwrap().is_none());
+    let missing = time("2022-11-04T00:00:00Z");
+    store.set_post_missing(1003, Some(missing)).unwrap();
+    assert_eq!(
+        store.bridged_post(1003).unwrap().unwrap().missing,
+        Some(missing)
+    );
+    store.set_post_missing(1003, None).unwrap();
+    assert_eq!(store.bridged_post(1003).unwrap().unwrap().missing, None);
+    let mut since = post_ids(&store.bridged_posts(12345, 1002).unwrap());
+    since.sort();
+    assert_eq!(since, vec![1002, 1003]);
+    assert_eq!(
+        post_ids(&store.posts_with_tag("ART", 0, 10).unwrap()),
+        vec![1003, 1002]
+    );
+    assert_eq!(
+        post_ids(&store.posts_with_tag("birds", 1, 10).unwrap()),
+        vec![1001]
+    );
+    assert_eq!(store.count_posts_with_tag("Birds").unwrap(), 2);
+    assert_eq!(store.count_posts().unwrap(), 3);
+
+    let deleted = time("2022-11-04T12:00:00Z");
+    store.delete_post(1002, deleted).unwrap();
+    assert_eq!(
+        store.bridged_post(1002).unwrap().unwrap().deleted,
+        Some(deleted)
+    );
+    let mut remaining = post_ids(&store.bridged_posts(12345, 0).unwrap());
+    remaining.sort();
+    assert_eq!(remaining, vec![1001, 1003]);
+    assert_eq!(
+        post_ids(&store.posts_with_tag("art", 0, 10).unwrap()),
+        [1003]
+    );
+    assert_eq!(store.count_posts_with_tag("birds").unwrap(), 1);
+    assert_eq!(store.count_posts().unwrap(), 2);
+
+    // Deliveries
+    store.save_delivery(&delivery("a", 0)).unwrap();
+    store.save_delivery(&delivery("b", 0)).unwrap();
+    store.save_delivery(&delivery("a", 3)).unwrap();
+    let mut deliveries = store.deliveries().unwrap();
+    deliveries.sort_by(|a, b| a.id.cmp(&b.id));
+    assert_eq!(deliveries.len(), 2);
+    assert_eq!(deliveries[0].attempts, 3);
+    assert_eq!(deliveries[0].activity, r#"{"type":"Create"}"#);
+    assert_eq!(deliveries[0].next_attempt, time("2022-11-01T12:05:00Z"));
+    store.remove_delivery("a").unwrap();
+    store.remove_delivery("missing").unwrap();
+    assert_eq!(delivery_ids(store), vec!["b"]);
+
+    // Remote actors
+    let id = "https://remote.test/users/alice";
+    assert!(store.cached_actor(id).unwrap().is_none());
+    for document in ["{}", r#"{"id":"alice"}"#] {
+        let actor = CachedActor {
+            document: document.to_string(),
+            fetched: time("2022-11-01T12:00:00Z"),
+        };
+        store.cache_actor(id, &actor).unwrap();
+    }
+    assert_eq!(
+        store.cached_actor(id).unwrap().unwrap().document,
+        r#"{"id":"alice"}"#
+    );
+}
+
+fn delivery_ids(store: &dyn Store) -> Vec<String> {
+    store
+        .deliveries()
+        .unwrap()
+        .into_iter()
+        .map(|delivery| delivery.id)
+        .collect()
+}
+
+#[test]
+fn memory_store_follows_the_contract() {
+    check_contract(&MemoryStore::new());
+}
+
+#[test]
+fn sqlite_store_follows_the_contract() {
+    check_contract(&SqliteStore::open_in_memory().unwrap());
+}
+
+#[test]
+fn sqlite_store_persists_to_a_private_file() {
+    let directory = TempDir::new("persist");
+    let path = directory.0.join("cobridge.sqlite3");
+    {
+        let store = SqliteStore::open(&path).unwrap();
+        check_contract(&store);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    let store = SqliteStore::open(&path).unwrap();
+    assert_eq!(store.key(12345).unwrap().as_deref(), Some("second key"));
+    assert_eq!(store.last_seen_post(12345).unwrap(), Some(1002));
+    assert_eq!(store.count_posts().unwrap(), 2);
+}
+
+/// Write the files the store kept before it used SQLite
+fn write_legacy_files(directory: &TempDir) {
+    directory.write("keys/12345.pem", "legacy key");
+    directory.write("keys/12345.pem.tmp", "half written key");
+    directory.write(
+        "followers/12345.json",
+        r#"{
+            "handle": "example-artist",
+            "followers": [
+                {"actor": "https://remote.test/users/alice", "inbox": "https://remote.test/a"},
+                {
+                    "actor": "https://remote.test/users/bob",
+                    "inbox": "https://remote.test/b",
+                    "sharedInbox": "https://remote.test/inbox"
+                }
+            ]
+        }"#,
+    );
+    directory.write("seen/12345", "1002\n");
+    directory.write(
+        "deliveries/1667304000000-0000000000000001.json",
+        &serde_json::to_string(&delivery("1667304000000-0000000000000001", 2)).unwrap(),
+    );
+}
+
+#[test]
+fn sqlite_store_imports_files_from_before_the_database() {
+    let directory = TempDir::new("import");
+    write_legacy_files(&directory);
+    let store = SqliteStore::open(&directory.0.join("cobridge.sqlite3")).unwrap();
+
+    assert_eq!(store.key(12345).unwrap().as_deref(), Some("legacy key"));
+    assert_eq!(store.count_projects().unwrap(), 1);
+    assert_eq!(
+        store.followed_projects().unwrap(),
+        vec![(12345, "example-artist".to_string())]
+    );
+    let mut followers = store.followers(12345).unwrap();
+    followers.sort_by(|a, b| a.actor.cmp(&b.actor));
+    assert_eq!(followers.len(), 2);
+    assert_eq!(followers[0].shared_inbox, None);
+    assert_eq!(
+        followers[1].shared_inbox.as_deref(),
+        Some("https://remote.test/inbox")
+    );
+    // Followers from then followed the actor IDs based on handles
+    for follower in &followers {
+        assert_eq!(follower.legacy_handle.as_deref(), Some("example-artist"));
+    }
+    assert_eq!(store.last_seen_post(12345).unwrap(), Some(1002));
+    let deliveries = store.deliveries().unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].attempts, 2);
+    drop(store);
+
+    // The import only happens once, with the first migration
+    std::fs::remove_file(directory.0.join("keys/12345.pem")).unwrap();
+    directory.write("keys/24680.pem", "new legacy key");
+    let store = SqliteStore::open(&directory.0.join("cobridge.sqlite3")).unwrap();
+    assert_eq!(store.key(12345).unwrap().as_deref(), Some("legacy key"));
+    assert_eq!(store.key(24680).unwrap(), None);
+}
+
+/// Create a database as an older version of the bridge would have left it,
+/// with only the first `migrations` migrations applied
+fn create_old_database(path: &Path, migrations: &[&str]) -> Connection {
+    let connection = Connection::open(path).unwrap();
+    for migration in migrations {
+        connection.execute_batch(migration).unwrap();
+    }
+    connection
+        .pragma_update(None, "user_version", migrations.len())
+        .unwrap();
+    connection
+}
+
+#[test]
+fn sqlite_store_migrates_old_databases() {
+    let directory = TempDir::new("migrate");
+    let path = directory.0.join("cobridge.sqlite3");
+    // Files which were already imported aren't imported again
+    directory.write("keys/24680.pem", "legacy key");
+    {
+        let connection = create_old_database(
+            &path,
+            &[
+                include_str!("../src/store/migrations/0001_initial.sql"),
+                include_str!("../src/store/migrations/0002_bridged_posts.sql"),
+            ],
+        );
+        connection
+            .execute(
+                "INSERT INTO keys (project_id, private_key_pem) VALUES (12345, 'old key')",
+                [],
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO projects (project_id, handle) VALUES (12345, 'example-artist')",
+                [],
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO followers (project_id, actor, inbox) VALUES (12345, ?, ?)",
+                params!["https://remote.test/users/alice", "https://remote.test/a"],
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO bridged_posts (post_id, project_id, handle, published)
+                 VALUES (1001, 12345, 'example-artist', ?)",
+                [time("2022-11-01T12:00:00Z")],
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO post_tags (post_id, tag) VALUES (1001, 'birds')",
+                [],
+            )
+            .unwrap();
+    }
+
+    let store = SqliteStore::open(&path).unwrap();
+    assert_eq!(store.key(12345).unwrap().as_deref(), Some("old key"));
+    assert_eq!(store.key(24680).unwrap(), None);
+    let followers = store.followers(12345).unwrap();
+    assert_eq!(followers.len(), 1);
+    assert_eq!(
+        followers[0].legacy_handle.as_deref(),
+        Some("example-artist")
+    );
+    assert!(store.former_handles(12345).unwrap().is_empty());
+    assert!(store.follow_requests(12345).unwrap().is_empty());
+
+    // Posts from before edits were noticed have no hash, and aren't deleted
+    let post = store.bridged_post(1001).unwrap().unwrap();
+    assert_eq!(post.content_hash, "");
+    assert_eq!(post.deleted, None);
+    assert_eq!(post.url, "");
+    assert!(post.private);
+    assert_eq!(
+        post_ids(&store.posts_with_tag("birds", 0, 10).unwrap()),
+        [1001]
+    );
+
+    // Everything works as usual after migrating
+    store.set_last_seen_post(12345, 1001).unwrap();
+    store
+        .delete_post(1001, time("2022-11-02T12:00:00Z"))
+        .unwrap();
+    assert_eq!(store.count_posts_with_tag("birds").unwrap(), 0);
+}